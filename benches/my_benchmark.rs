@@ -24,6 +24,11 @@ fn blocked_knightrider_bench(c: &mut Criterion) {
 fn convoluted_bench(c: &mut Criterion) {
     c.bench_function("convoluted", |b| b.iter(convoluted));
 }
+fn convoluted_bidirectional_bench(c: &mut Criterion) {
+    c.bench_function("convoluted_bidirectional", |b| {
+        b.iter(convoluted_bidirectional)
+    });
+}
 
 criterion_group!(
     benches,
@@ -34,14 +39,15 @@ criterion_group!(
     infinte_king_bench,
     skirmisher_bench,
     blocked_knightrider_bench,
-    convoluted_bench
+    convoluted_bench,
+    convoluted_bidirectional_bench
 );
 criterion_main!(benches);
 
 //copied from the tests in the main crate
 use std::vec;
 
-use fairy_chess::{check_move, MoveCompact, MoveGraph};
+use fairy_chess::{check_move, check_move_bidirectional, MoveCompact, MoveGraph, ReverseMoveGraph};
 
 struct TestBoard {
     x_max: i32,
@@ -313,3 +319,31 @@ fn convoluted() {
 
     assert_eq!(valids, vec![(-1, 3), (3, 3), (7, 3), (9, 5), (11, 7)])
 }
+
+fn convoluted_bidirectional() {
+    let piece = &MoveGraph::from(("([2,2]^[2..*]-|/*[0,-4])^*".parse::<MoveCompact>()).unwrap());
+    let reverse = &ReverseMoveGraph::from(piece);
+
+    let points_r = (-1..=11).collect::<Vec<i32>>();
+    let grid_points = points_r
+        .iter()
+        .flat_map(|x| points_r.iter().map(|y| (*x, *y)))
+        .filter(|x| !matches!(x, (1, 9) | (3, 11) | (5, 1) | (5, 9) | (9, 1) | (11, 7))) //blocking pieces
+        .collect::<Vec<(i32, i32)>>();
+
+    let board = &DetailedTestBoard { grid: grid_points };
+
+    let start_position = (7, 3);
+
+    let points = points_r
+        .iter()
+        .flat_map(|x| points_r.iter().map(|y| (*x, *y)));
+    let valids: Vec<(i32, i32)> = points
+        .filter(|p| {
+            check_move_bidirectional(piece, reverse, board, start_position, *p, false, false)
+                .is_some()
+        })
+        .collect();
+
+    assert_eq!(valids, vec![(-1, 3), (3, 3), (7, 3), (9, 5), (11, 7)])
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use crate::movespec::OnBoard;
+use crate::{generate_oriented, Board, MoveGraph, OrientedGraph};
+
+/// A piece on the board, as needed to find the squares it attacks
+#[derive(Debug, Clone, Copy)]
+pub struct AttackEntry<'a> {
+    pub piece: &'a MoveGraph,
+    pub start_position: (i32, i32),
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+/// For each attacked position, the indices (into the entries the map was built from) of the pieces attacking it, in ascending order
+pub type AttackMap = HashMap<(i32, i32), Vec<usize>>;
+
+/**
+Builds the attack map of a set of pieces: for every position, which of the pieces could finish a move there.
+As with `generate_moves`, positions are not filtered; a piece "attacks" squares holding friendly pieces (i.e defends them),
+and any out-of-bounds positions it could otherwise land on.
+Each piece is searched on its own, as where it can go depends on where it starts. Entries that share a `MoveGraph` and orientation
+share the work of preparing the graph for searching, so pieces of the same type should be given the same `MoveGraph`.
+*/
+pub fn attack_map<B>(entries: &[AttackEntry], board: &B) -> AttackMap
+where
+    B: Board,
+{
+    //there are few types of piece, so the prepared graphs are looked through rather than hashed.
+    //they are told apart by address: the graph resolved for the board is shared by every clone of a board-relative graph
+    let mut graphs: Vec<(OnBoard<MoveGraph>, bool, bool, OrientedGraph)> = Vec::new();
+    let mut map: AttackMap = HashMap::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let piece = entry.piece.on_board(board);
+        let prepared = graphs.iter().position(|(g, invert_x, invert_y, _)| {
+            (*invert_x, *invert_y) == (entry.invert_x, entry.invert_y)
+                && std::ptr::eq(&**g, &*piece)
+        });
+        let graph = match prepared {
+            Some(prepared) => &graphs[prepared].3,
+            None => {
+                let graph = OrientedGraph::new(&piece, entry.invert_x, entry.invert_y);
+                graphs.push((piece, entry.invert_x, entry.invert_y, graph));
+                &graphs.last().unwrap().3
+            }
+        };
+
+        for position in generate_oriented(graph, board, entry.start_position).into_keys() {
+            map.entry(position).or_default().push(i);
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{attack_map, check_move, AttackEntry, MoveCompact, MoveGraph};
+
+    struct PiecesBoard {
+        pieces: Vec<(i32, i32)>,
+    }
+
+    impl crate::Board for PiecesBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            if position.0 < 0
+                || position.0 > 7
+                || position.1 < 0
+                || position.1 > 7
+                || self.pieces.contains(&position)
+            {
+                crate::TileState::Impassable
+            } else {
+                crate::TileState::Empty
+            }
+        }
+    }
+
+    #[test]
+    fn matches_check_move() {
+        let rook = &MoveGraph::from("[1,0]^*|-/".parse::<MoveCompact>().unwrap());
+        let pawn = &MoveGraph::from("[1,1]|".parse::<MoveCompact>().unwrap());
+
+        let entries = [
+            AttackEntry {
+                piece: rook,
+                start_position: (0, 0),
+                invert_x: false,
+                invert_y: false,
+            },
+            AttackEntry {
+                piece: pawn,
+                start_position: (3, 1),
+                invert_x: false,
+                invert_y: false,
+            },
+            AttackEntry {
+                piece: rook,
+                start_position: (7, 3),
+                invert_x: false,
+                invert_y: false,
+            },
+            AttackEntry {
+                piece: pawn,
+                start_position: (4, 6),
+                invert_x: false,
+                invert_y: true,
+            },
+        ];
+        let board = &PiecesBoard {
+            pieces: entries.iter().map(|e| e.start_position).collect(),
+        };
+
+        //every piece is on the board, so each is blocked by the others
+        let map = attack_map(&entries, board);
+
+        for x in 0..=7 {
+            for y in 0..=7 {
+                let expected: Vec<usize> = entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| {
+                        check_move(
+                            e.piece,
+                            board,
+                            e.start_position,
+                            (x, y),
+                            e.invert_x,
+                            e.invert_y,
+                        )
+                        .is_some()
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+                assert_eq!(
+                    map.get(&(x, y)).cloned().unwrap_or_default(),
+                    expected,
+                    "{:?}",
+                    (x, y)
+                );
+            }
+        }
+
+        assert_eq!(map[&(2, 2)], vec![1]);
+        assert_eq!(map[&(3, 5)], vec![3]);
+        assert_eq!(map[&(7, 0)], vec![0, 2]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::{generate_moves_tracked, Board, MoveGraph, Moves, TileState};
+
+/// Wraps a `Board`, recording every position whose state was asked for.
+pub struct ReadTracker<'a, B: Board> {
+    board: &'a B,
+    reads: RefCell<HashSet<(i32, i32)>>,
+}
+
+impl<'a, B: Board> ReadTracker<'a, B> {
+    pub fn new(board: &'a B) -> Self {
+        ReadTracker {
+            board,
+            reads: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// The positions read so far
+    pub fn reads(&self) -> HashSet<(i32, i32)> {
+        self.reads.borrow().clone()
+    }
+
+    pub fn into_reads(self) -> HashSet<(i32, i32)> {
+        self.reads.into_inner()
+    }
+}
+
+impl<'a, B: Board> Board for ReadTracker<'a, B> {
+    fn tile_at(&self, position: (i32, i32)) -> TileState {
+        self.reads.borrow_mut().insert(position);
+        self.board.tile_at(position)
+    }
+
+    fn dimensions(&self) -> Option<(i32, i32)> {
+        self.board.dimensions()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    start_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+    moves: Moves,
+    read_set: HashSet<(i32, i32)>,
+}
+
+/**
+Caches the generated moves of pieces between board states.
+Each entry remembers the squares it read from the board; after a move is made, pass the squares that changed to `invalidate`,
+and only the entries that depended on them will be regenerated.
+The key should identify both the piece and its type; the start position and inversion are checked by the cache itself.
+*/
+#[derive(Debug)]
+pub struct MoveCache<K> {
+    entries: HashMap<K, CacheEntry>,
+    //which entries read each square
+    readers: HashMap<(i32, i32), HashSet<K>>,
+}
+
+impl<K> Default for MoveCache<K> {
+    fn default() -> Self {
+        MoveCache {
+            entries: HashMap::new(),
+            readers: HashMap::new(),
+        }
+    }
+}
+
+impl<K> MoveCache<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached moves for this key, if they have not been invalidated
+    pub fn get(&self, key: &K) -> Option<&Moves> {
+        self.entries.get(key).map(|e| &e.moves)
+    }
+
+    /// Returns the moves for this key, generating them (as `generate_moves` does) if they are not cached,
+    /// or if the cached entry was made for a different start position or inversion
+    pub fn get_or_generate<B>(
+        &mut self,
+        key: K,
+        piece: &MoveGraph,
+        board: &B,
+        start_position: (i32, i32),
+        invert_x: bool,
+        invert_y: bool,
+    ) -> &Moves
+    where
+        B: Board,
+    {
+        let stale = match self.entries.get(&key) {
+            Some(e) => {
+                e.start_position != start_position
+                    || e.invert_x != invert_x
+                    || e.invert_y != invert_y
+            }
+            None => true,
+        };
+
+        if stale {
+            self.remove(&key);
+            let (moves, read_set) =
+                generate_moves_tracked(piece, board, start_position, invert_x, invert_y);
+            for p in &read_set {
+                self.readers.entry(*p).or_default().insert(key.clone());
+            }
+            self.entries.insert(
+                key.clone(),
+                CacheEntry {
+                    start_position,
+                    invert_x,
+                    invert_y,
+                    moves,
+                    read_set,
+                },
+            );
+        }
+
+        &self.entries[&key].moves
+    }
+
+    /// Drops every entry that read one of the changed squares. Returns the number of entries dropped.
+    pub fn invalidate<I>(&mut self, changed: I) -> usize
+    where
+        I: IntoIterator<Item = (i32, i32)>,
+    {
+        let stale: HashSet<K> = changed
+            .into_iter()
+            .filter_map(|p| self.readers.remove(&p))
+            .flatten()
+            .collect();
+
+        stale.iter().filter(|k| self.remove(k)).count()
+    }
+
+    /// Drops the entry for this key. Returns whether there was one.
+    pub fn remove(&mut self, key: &K) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                for p in entry.read_set {
+                    if let Some(keys) = self.readers.get_mut(&p) {
+                        keys.remove(key);
+                        if keys.is_empty() {
+                            self.readers.remove(&p);
+                        }
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.readers.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_moves_tracked, MoveCache, MoveCompact, MoveGraph};
+
+    struct BlockedBoard {
+        blocked: Vec<(i32, i32)>,
+    }
+
+    impl crate::Board for BlockedBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            if position.0 < 0
+                || position.0 > 7
+                || position.1 < 0
+                || position.1 > 7
+                || self.blocked.contains(&position)
+            {
+                crate::TileState::Impassable
+            } else {
+                crate::TileState::Empty
+            }
+        }
+    }
+
+    #[test]
+    fn read_set() {
+        let rook = &MoveGraph::from("[1,0]^*|-/".parse::<MoveCompact>().unwrap());
+        let board = &BlockedBoard {
+            blocked: vec![(3, 5)],
+        };
+
+        let (moves, reads) = generate_moves_tracked(rook, board, (3, 2), false, false);
+
+        assert!(moves.contains_key(&(3, 5)));
+        assert!(!moves.contains_key(&(3, 6)));
+        assert_eq!(moves[&(3, 4)], vec![(3, 2), (3, 3), (3, 4)]);
+        //the rook sees the blocker, but nothing beyond it
+        assert!(reads.contains(&(3, 5)));
+        assert!(!reads.contains(&(3, 6)));
+        assert!(!reads.contains(&(4, 4)));
+    }
+
+    #[test]
+    fn invalidation() {
+        let rook = &MoveGraph::from("[1,0]^*|-/".parse::<MoveCompact>().unwrap());
+        let knight = &MoveGraph::from("[1,2]|-/".parse::<MoveCompact>().unwrap());
+        let mut board = BlockedBoard {
+            blocked: vec![(3, 5)],
+        };
+
+        let mut cache = MoveCache::new();
+        assert!(!cache
+            .get_or_generate("rook", rook, &board, (3, 2), false, false)
+            .contains_key(&(3, 6)));
+        cache.get_or_generate("knight", knight, &board, (0, 0), false, false);
+        assert_eq!(cache.len(), 2);
+
+        //moving the blocker away only concerns the rook
+        board.blocked = vec![(6, 6)];
+        assert_eq!(cache.invalidate(vec![(3, 5), (6, 6)]), 1);
+        assert!(cache.get(&"rook").is_none());
+        assert!(cache.get(&"knight").is_some());
+
+        assert!(cache
+            .get_or_generate("rook", rook, &board, (3, 2), false, false)
+            .contains_key(&(3, 6)));
+
+        //a square neither piece looked at
+        assert_eq!(cache.invalidate(vec![(7, 7)]), 0);
+        assert_eq!(cache.len(), 2);
+
+        //moving the piece itself regenerates the entry
+        assert!(cache
+            .get_or_generate("knight", knight, &board, (1, 0), false, false)
+            .contains_key(&(0, 2)));
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use thiserror::Error;
+
+use crate::{generate_moves, Board, MoveCompact, MoveGraph, TileState};
+
+/// An optimisation pass over a `MoveGraph`. None of these should change the moves the graph accepts.
+#[derive(Debug, Clone, Copy)]
+pub enum Pass {
+    /// Merges multi-layer choices into single choice nodes, and removes lone dummy edges, until neither can be done.
+    /// This is what `MoveGraph::from` does.
+    Deflate,
+    /// Merges multi-layer choices into single choice nodes
+    MergeChoiceLayers,
+    /// Merges the nodes at either end of a required dummy edge, where it is the only edge leaving its source
+    CollapseDummyEdges,
+    /// A pass supplied by the user, with a name to identify it in dumps and errors
+    Custom(&'static str, fn(&mut MoveGraph)),
+}
+
+impl Pass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Deflate => "deflate",
+            Pass::MergeChoiceLayers => "merge choice layers",
+            Pass::CollapseDummyEdges => "collapse dummy edges",
+            Pass::Custom(name, _) => name,
+        }
+    }
+
+    pub(crate) fn run(&self, graph: &mut MoveGraph) {
+        match self {
+            Pass::Deflate => graph.deflate(),
+            Pass::MergeChoiceLayers => while graph.merge_choice_layers() {},
+            Pass::CollapseDummyEdges => while graph.collapse_dummy_edge() {},
+            Pass::Custom(_, f) => f(graph),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Graphviz DOT
+    Dot,
+    /// One line for the head, then one line per edge
+    Text,
+}
+
+/// The graph as it was after one stage of compilation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageDump {
+    /// "build" for the graph before any passes, otherwise the name of the pass
+    pub stage: &'static str,
+    pub graph: String,
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum CompileError {
+    #[error("pass \"{pass}\" changed the moves from {start:?} on the sample board: lost {lost:?}, gained {gained:?}")]
+    MovesChanged {
+        pass: &'static str,
+        start: (i32, i32),
+        lost: Vec<(i32, i32)>,
+        gained: Vec<(i32, i32)>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Compiled {
+    pub graph: MoveGraph,
+    /// Empty unless dumping was asked for
+    pub dumps: Vec<StageDump>,
+}
+
+/**
+Compiles a `MoveCompact` into a `MoveGraph`, by building the graph and then running a sequence of passes over it.
+The default compiler just deflates the graph, which is what `MoveGraph::from` does.
+*/
+pub struct Compiler<'a> {
+    passes: Vec<Pass>,
+    dump: Option<DumpFormat>,
+    sample: Option<Sample<'a>>,
+}
+
+/// The board and start positions that passes are verified on
+struct Sample<'a> {
+    board: &'a dyn Board,
+    start_positions: Vec<(i32, i32)>,
+}
+
+impl<'a> Default for Compiler<'a> {
+    fn default() -> Self {
+        Compiler {
+            passes: vec![Pass::Deflate],
+            dump: None,
+            sample: None,
+        }
+    }
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the passes to run, in order. Pass an empty list to keep the graph as built.
+    pub fn passes(mut self, passes: &[Pass]) -> Self {
+        self.passes = passes.to_vec();
+        self
+    }
+
+    /// Records the graph after it is built, and after every pass, in the given format
+    pub fn dump(mut self, format: DumpFormat) -> Self {
+        self.dump = Some(format);
+        self
+    }
+
+    /**
+    Debug mode: after every pass, generates the moves of the graph from each of the start positions on the sample board,
+    and fails compilation if they differ from those of the graph as it was built.
+    */
+    pub fn verify_on(mut self, board: &'a dyn Board, start_positions: Vec<(i32, i32)>) -> Self {
+        self.sample = Some(Sample {
+            board,
+            start_positions,
+        });
+        self
+    }
+
+    /**
+    Compiles the move. A move with exponents that depend on the size of the board is built again for each size of board it is searched on,
+    so it is the graph for the size of the sample board (or for a board that doesn't report its size, if there is no sample)
+    that the passes are run on, dumped and verified. The graphs for other sizes are compiled with the same passes when they are first searched.
+    */
+    pub fn compile(&self, input: &MoveCompact) -> Result<Compiled, CompileError> {
+        let mut dumps = Vec::new();
+        let mut graph = MoveGraph::build(input);
+        if graph.is_board_relative() {
+            let dimensions = self.sample.as_ref().and_then(|s| s.board.dimensions());
+            let on_board = self.run(MoveGraph::build(&input.on_board(dimensions)), &mut dumps)?;
+            graph.set_passes(&self.passes);
+            graph.set_on_board(dimensions, on_board);
+        } else {
+            graph = self.run(graph, &mut dumps)?;
+        }
+        Ok(Compiled { graph, dumps })
+    }
+
+    /// Runs the passes over the graph, recording it after it is built and after every pass, and verifying every pass on the sample
+    fn run(
+        &self,
+        mut graph: MoveGraph,
+        dumps: &mut Vec<StageDump>,
+    ) -> Result<MoveGraph, CompileError> {
+        self.record(dumps, "build", &graph);
+
+        let expected = self.sample_moves(&graph);
+
+        for pass in &self.passes {
+            pass.run(&mut graph);
+            self.record(dumps, pass.name(), &graph);
+
+            let actual = self.sample_moves(&graph);
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                if *expected != actual {
+                    let mut lost: Vec<(i32, i32)> = expected.difference(&actual).copied().collect();
+                    let mut gained: Vec<(i32, i32)> =
+                        actual.difference(expected).copied().collect();
+                    lost.sort();
+                    gained.sort();
+                    return Err(CompileError::MovesChanged {
+                        pass: pass.name(),
+                        start: self.sample.as_ref().unwrap().start_positions[i],
+                        lost,
+                        gained,
+                    });
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn record(&self, dumps: &mut Vec<StageDump>, stage: &'static str, graph: &MoveGraph) {
+        if let Some(format) = self.dump {
+            dumps.push(StageDump {
+                stage,
+                graph: dump_graph(graph, format),
+            });
+        }
+    }
+
+    /// The positions the graph accepts from each start position of the sample, or nothing if there is no sample
+    fn sample_moves(&self, graph: &MoveGraph) -> Vec<HashSet<(i32, i32)>> {
+        match &self.sample {
+            Some(sample) => sample
+                .start_positions
+                .iter()
+                .map(|s| {
+                    generate_moves(graph, &SampleBoard(sample.board), *s, false, false)
+                        .into_keys()
+                        .collect()
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The graph of a move for a board of these dimensions, compiled with these passes.
+/// `MoveGraph::on_board` builds the graphs of moves with exponents that depend on the size of the board through this.
+pub(crate) fn compile_on_board(
+    passes: &[Pass],
+    input: &MoveCompact,
+    dimensions: Option<(i32, i32)>,
+) -> MoveGraph {
+    let compiler = Compiler::new().passes(passes);
+    match compiler.run(
+        MoveGraph::build(&input.on_board(dimensions)),
+        &mut Vec::new(),
+    ) {
+        Ok(graph) => graph,
+        //there is no sample to verify the passes on, so they can't fail
+        Err(_) => unreachable!(),
+    }
+}
+
+struct SampleBoard<'a>(&'a dyn Board);
+
+impl<'a> Board for SampleBoard<'a> {
+    fn tile_at(&self, position: (i32, i32)) -> TileState {
+        self.0.tile_at(position)
+    }
+
+    fn dimensions(&self) -> Option<(i32, i32)> {
+        self.0.dimensions()
+    }
+}
+
+pub fn dump_graph(graph: &MoveGraph, format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Dot => format!("{:?}", petgraph::dot::Dot::with_config(&graph.graph, &[])),
+        DumpFormat::Text => {
+            let mut out = format!("head {}\n", graph.head().index());
+            for e in graph.graph.edge_references() {
+                //writing to a string cannot fail
+                let _ = writeln!(
+                    out,
+                    "{} -> {} {:?}",
+                    e.source().index(),
+                    e.target().index(),
+                    e.weight()
+                );
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+
+    use crate::compiler::{CompileError, Compiler, DumpFormat, Pass};
+    use crate::{generate_moves, MoveCompact, MoveGraph};
+
+    struct TestBoard {
+        blocked: Vec<(i32, i32)>,
+    }
+
+    impl crate::Board for TestBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            if position.0 < 0
+                || position.0 > 9
+                || position.1 < 0
+                || position.1 > 9
+                || self.blocked.contains(&position)
+            {
+                crate::TileState::Impassable
+            } else {
+                crate::TileState::Empty
+            }
+        }
+
+        fn dimensions(&self) -> Option<(i32, i32)> {
+            Some((10, 10))
+        }
+    }
+
+    #[test]
+    fn passes_preserve_moves() {
+        let board = TestBoard {
+            blocked: vec![(2, 3), (3, 3), (6, 6)],
+        };
+        for spec in [
+            "[1,2]|-/",
+            "[1,2]^*|-/",
+            "[1,2]|-/*[0,1]?",
+            "([2,2]^[2..*]-|/*[0,-4])^*",
+            "{[1,0]/,[1,1]}|-^*",
+        ] {
+            let input = spec.parse::<MoveCompact>().unwrap();
+            for passes in [
+                vec![],
+                vec![Pass::Deflate],
+                vec![Pass::MergeChoiceLayers],
+                vec![Pass::CollapseDummyEdges, Pass::MergeChoiceLayers],
+            ] {
+                let compiled = Compiler::new()
+                    .passes(&passes)
+                    .verify_on(&board, vec![(1, 1), (4, 5)])
+                    .compile(&input);
+                assert!(compiled.is_ok(), "{} {:?}", spec, passes);
+            }
+
+            //the default compiler is the same as MoveGraph::from
+            let default = Compiler::new().compile(&input).unwrap().graph;
+            assert_eq!(
+                default.graph.edge_count(),
+                MoveGraph::from(&input).graph.edge_count()
+            );
+        }
+    }
+
+    #[test]
+    fn dumps() {
+        let input = "[1,2]|-/".parse::<MoveCompact>().unwrap();
+        let compiled = Compiler::new()
+            .passes(&[Pass::MergeChoiceLayers, Pass::CollapseDummyEdges])
+            .dump(DumpFormat::Text)
+            .compile(&input)
+            .unwrap();
+
+        let stages: Vec<&str> = compiled.dumps.iter().map(|d| d.stage).collect();
+        assert_eq!(
+            stages,
+            vec!["build", "merge choice layers", "collapse dummy edges"]
+        );
+        //the built graph has dummy edges for the choices of the mirrors; the deflated one is just the 8 jumps
+        assert!(compiled.dumps[0].graph.contains("DummyRequired"));
+        assert!(!compiled.dumps[2].graph.contains("DummyRequired"));
+        assert_eq!(compiled.dumps[2].graph.lines().count(), 9);
+
+        let dot = Compiler::new()
+            .dump(DumpFormat::Dot)
+            .compile(&input)
+            .unwrap()
+            .dumps;
+        assert_eq!(dot.len(), 2);
+        assert!(dot[1].graph.starts_with("digraph"));
+    }
+
+    #[test]
+    fn verification_catches_broken_pass() {
+        fn drop_an_edge(graph: &mut MoveGraph) {
+            let e = graph.graph.edge_references().next().unwrap().id();
+            graph.graph.remove_edge(e);
+        }
+
+        let board = TestBoard { blocked: vec![] };
+        let input = "[1,2]|-/".parse::<MoveCompact>().unwrap();
+        let result = Compiler::new()
+            .passes(&[Pass::Deflate, Pass::Custom("drop an edge", drop_an_edge)])
+            .verify_on(&board, vec![(4, 4)])
+            .compile(&input);
+
+        match result {
+            Err(CompileError::MovesChanged {
+                pass,
+                start,
+                lost,
+                gained,
+            }) => {
+                assert_eq!(pass, "drop an edge");
+                assert_eq!(start, (4, 4));
+                assert_eq!(lost.len(), 1);
+                assert!(gained.is_empty());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn board_relative_moves_are_verified_on_the_sample_board() {
+        fn drop_every_edge(graph: &mut MoveGraph) {
+            graph.graph.clear_edges();
+        }
+
+        let board = TestBoard { blocked: vec![] };
+        let input = "[1,0]^[1..w/2]".parse::<MoveCompact>().unwrap();
+        let result = Compiler::new()
+            .passes(&[
+                Pass::Deflate,
+                Pass::Custom("drop every edge", drop_every_edge),
+            ])
+            .verify_on(&board, vec![(1, 1)])
+            .compile(&input);
+        assert_eq!(
+            result.unwrap_err(),
+            CompileError::MovesChanged {
+                pass: "drop every edge",
+                start: (1, 1),
+                lost: vec![(2, 1), (3, 1), (4, 1), (5, 1), (6, 1)],
+                gained: vec![(1, 1)],
+            }
+        );
+
+        //the dumps are of the graph built for the sample board, with the exponents resolved: 1 to 5 jumps
+        let dumps = Compiler::new()
+            .dump(DumpFormat::Text)
+            .verify_on(&board, vec![(1, 1)])
+            .compile(&input)
+            .unwrap()
+            .dumps;
+        assert_eq!(dumps[0].graph.matches("Jump(").count(), 15);
+    }
+
+    #[test]
+    fn board_relative_moves_keep_passes() {
+        fn drop_every_edge(graph: &mut MoveGraph) {
+            graph.graph.clear_edges();
+        }
+
+        let board = TestBoard { blocked: vec![] };
+        let input = "[1,0]^[1..w/2]".parse::<MoveCompact>().unwrap();
+        assert_eq!(
+            generate_moves(&MoveGraph::from(&input), &board, (1, 1), false, false).len(),
+            5
+        );
+
+        //the graph built for the board is compiled the same way as the one that was asked for,
+        //so all that is left is the move that goes nowhere
+        let compiled = Compiler::new()
+            .passes(&[
+                Pass::Deflate,
+                Pass::Custom("drop every edge", drop_every_edge),
+            ])
+            .compile(&input)
+            .unwrap()
+            .graph;
+        for _ in 0..2 {
+            assert_eq!(
+                generate_moves(&compiled, &board, (1, 1), false, false)
+                    .keys()
+                    .collect::<Vec<_>>(),
+                vec![&(1, 1)]
+            );
+        }
+    }
+}
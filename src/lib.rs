@@ -1,26 +1,114 @@
+mod attack;
+mod cache;
+mod compiler;
 mod movespec;
 mod parser;
+mod retro;
 
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use petgraph::graph::{DefaultIx, NodeIndex};
-use petgraph::stable_graph::EdgeReference;
-
-pub use movespec::{EdgeType, Jump, Mod, MoveCompact, MoveGraph};
+use petgraph::visit::NodeIndexable;
+
+pub use attack::{attack_map, AttackEntry, AttackMap};
+pub use cache::{MoveCache, ReadTracker};
+pub use compiler::{dump_graph, CompileError, Compiled, Compiler, DumpFormat, Pass, StageDump};
+use movespec::turn;
+pub use movespec::{
+    migrate_sequences, Bindings, Bound, Component, EdgeType, InstantiationError, Jump, Mod,
+    MoveCompact, MoveGraph, NotationOptions, ParsingError, Position,
+};
+pub use retro::{check_move_bidirectional, retro_moves, ReverseMoveGraph};
 
 #[derive(Debug)]
 pub enum PieceCreationError {
     ParserError(parser::ParsingError),
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum TileState {
     Empty,
     Impassable,
+    /// Occupied by a piece that the moving piece cannot capture. This blocks the piece like an impassable tile,
+    /// but unlike one (e.g the edge of the board) it can be used as a hurdle, or passed through by a phasing move.
+    Friendly,
+    /// Occupied by a piece that the moving piece could capture. Like an impassable tile, this blocks the piece from moving through it.
+    /// Boards that report enemy pieces as impassable will work as before, but capture-only, hurdle and phasing moves need enemies to be reported as such.
+    Enemy,
+}
+
+/// A test of the state of a tile, made by a move before it can continue
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TileCondition {
+    /// The tile must be empty
+    Empty,
+    /// The tile must hold an enemy piece
+    Enemy,
+    /// The tile must hold a piece, friendly or enemy
+    Occupied,
+    /// The tile must be impassable, e.g off the edge of the board
+    Impassable,
+}
+
+impl TileCondition {
+    pub fn holds(&self, tile: TileState) -> bool {
+        match self {
+            TileCondition::Empty => tile == TileState::Empty,
+            TileCondition::Enemy => tile == TileState::Enemy,
+            TileCondition::Occupied => matches!(tile, TileState::Friendly | TileState::Enemy),
+            TileCondition::Impassable => tile == TileState::Impassable,
+        }
+    }
 }
 
+/// The positions a piece can finish a move on, each with the path taken to get there
+pub type Moves = HashMap<(i32, i32), Vec<(i32, i32)>>;
+
 pub trait Board {
     fn tile_at(&self, position: (i32, i32)) -> TileState; //returns the state of the board
+
+    /// The width and height of the board, which covers the positions from (0, 0) up to but not including them.
+    /// Exponents bounded by the width or height of the board need this; boards that don't report it can't make those moves.
+    fn dimensions(&self) -> Option<(i32, i32)> {
+        None
+    }
+}
+
+/// The board as the moving piece sees it: its start position is empty, however the board shows it, as the piece has left it
+pub(crate) struct Vacated<'a, B> {
+    pub(crate) board: &'a B,
+    pub(crate) start_position: (i32, i32),
+}
+
+impl<'a, B: Board> Board for Vacated<'a, B> {
+    fn tile_at(&self, position: (i32, i32)) -> TileState {
+        match position == self.start_position {
+            true => TileState::Empty,
+            false => self.board.tile_at(position),
+        }
+    }
+
+    fn dimensions(&self) -> Option<(i32, i32)> {
+        self.board.dimensions()
+    }
+}
+
+/// Whether a step from the position would take the piece off the board across each axis.
+/// None if the board doesn't report its dimensions, or the piece isn't on it to begin with
+fn leaves_board<B>(board: &B, position: (i32, i32), step: Jump) -> Option<(bool, bool)>
+where
+    B: Board,
+{
+    let (width, height) = board.dimensions()?;
+    let on_board = |(x, y): (i32, i32)| ((0..width).contains(&x), (0..height).contains(&y));
+    match on_board(position) {
+        (true, true) => {
+            let (x, y) = on_board((position.0 + step.x, position.1 + step.y));
+            Some((!x, !y))
+        }
+        _ => None,
+    }
 }
 
 #[derive(Debug)]
@@ -28,6 +116,76 @@ struct MoveTrace<Ix> {
     pub current_move: NodeIndex<Ix>,
     pub current_position: (i32, i32),
     pub trace: Rc<Trace<(i32, i32)>>,
+    //whether the piece is passing through the tile it is on (as a hurdle, or by phasing), and so may jump away from it even if it is occupied
+    //when searching backwards, whether the piece must have been passing through it
+    pub passing: bool,
+    //the positions the piece has captured on so far; searching backwards does not track these
+    pub captures: Rc<Trace<(i32, i32)>>,
+    //the labels the move has crossed so far, as indices into the graph's labels; searching backwards does not track these either
+    pub labels: Rc<Trace<usize>>,
+    //the shot the piece is making, if it is making one
+    pub shot: Option<Rc<Shot>>,
+    //the last leg of the move (or of the shot, while making one), which relative steps are turned from
+    //when searching backwards, the leg the piece must have made last, if that is known yet
+    pub heading: Option<(i32, i32)>,
+}
+
+/// Where a piece was when it started a shot, which it returns to once the shot is done
+#[derive(Debug)]
+struct Shot {
+    origin: (i32, i32),
+    trace: Rc<Trace<(i32, i32)>>,
+    passing: bool,
+    heading: Option<(i32, i32)>,
+}
+
+/// The part of a trace that decides where it can go from here
+type State = (
+    (i32, i32),
+    NodeIndex<DefaultIx>,
+    bool,
+    Option<(i32, i32)>,
+    Option<(i32, i32)>,
+);
+
+impl MoveTrace<DefaultIx> {
+    /// A trace that has started a move from position, but not made any of it yet
+    fn root(node: NodeIndex<DefaultIx>, position: (i32, i32)) -> Self {
+        MoveTrace {
+            current_move: node,
+            current_position: position,
+            trace: Rc::new(Trace::Root),
+            passing: false,
+            captures: Rc::new(Trace::Root),
+            labels: Rc::new(Trace::Root),
+            shot: None,
+            heading: None,
+        }
+    }
+
+    /// This trace, having followed an edge to node without moving
+    fn follow(&self, node: NodeIndex<DefaultIx>) -> Self {
+        MoveTrace {
+            current_move: node,
+            current_position: self.current_position,
+            trace: self.trace.clone(),
+            passing: self.passing,
+            captures: self.captures.clone(),
+            labels: self.labels.clone(),
+            shot: self.shot.clone(),
+            heading: self.heading,
+        }
+    }
+
+    fn state(&self) -> State {
+        (
+            self.current_position,
+            self.current_move,
+            self.passing,
+            self.shot.as_ref().map(|s| s.origin),
+            self.heading,
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -59,7 +217,7 @@ where
         let mut output = vec![T::default(); depth]; //TODO I'd rather not even set a value here; I'm overwriting them anyway in a second!
 
         let mut cur = &trace;
-        for i in (0..=depth - 1).rev() {
+        for i in (0..depth).rev() {
             match cur {
                 Trace::Root => {
                     // should never happen
@@ -78,6 +236,8 @@ where
 
 /**
 Assumes that target_position is not impassable (i.e open tile with no friendly piece)
+The start position is treated as empty for the whole move, as the piece being moved has left it.
+The board can then show every piece, including the one being moved, which is what `attack_map` and `retro_moves` are given.
 */
 pub fn check_move<B>(
     piece: &MoveGraph,
@@ -90,181 +250,373 @@ pub fn check_move<B>(
 where
     B: Board,
 {
-    //breadth-first search with a vector storing the points we have visited before (and therefore don't need to visit again)
-    //using BFS rather than depth-first should mean we'll find the shortest route
-    //(not necessarily, becuase of how choices aren't immediately evaluted, but it shouldnt be worst-case)
-    //and don't have to pop off the vector of visited positions when we roll back the loop
+    let piece = piece.on_board(board);
+    let graph = OrientedGraph::new(&piece, invert_x, invert_y);
+    search(&graph, board, start_position, |head| {
+        head.current_position == target_position
+    })
+    .map(|head| Vec::<(i32, i32)>::from(Trace::Node(head.current_position, head.trace)))
+}
+
+/// A legal move, along with the positions it captured on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturingMove {
+    pub path: Vec<(i32, i32)>,
+    /// The positions of the captures made with the capture modifier, in the order they were made.
+    /// This includes the target position only if the move was marked as capturing there.
+    pub captures: Vec<(i32, i32)>,
+}
+
+/**
+As `check_move`, but also returns the positions the move captured on, so that the pieces on them can be removed.
+The board is not changed during the search, so a later leg that lands on a position already captured on still finds the captured piece there.
+*/
+pub fn check_move_captures<B>(
+    piece: &MoveGraph,
+    board: &B,
+    start_position: (i32, i32),
+    target_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> Option<CapturingMove>
+where
+    B: Board,
+{
+    let piece = piece.on_board(board);
+    let graph = OrientedGraph::new(&piece, invert_x, invert_y);
+    search(&graph, board, start_position, |head| {
+        head.current_position == target_position
+    })
+    .map(|head| CapturingMove {
+        path: Vec::<(i32, i32)>::from(Trace::Node(head.current_position, head.trace)),
+        captures: Vec::<(i32, i32)>::from((*head.captures).clone()),
+    })
+}
+
+/// A legal move, along with the labels of the parts of the move it was made with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelledMove {
+    pub path: Vec<(i32, i32)>,
+    /// Every label the move crossed, in the order it crossed them; a label on a repeated part is listed once per repetition
+    pub labels: Vec<String>,
+}
+
+/// As `check_move`, but also returns the labels of the parts of the move that were made
+pub fn check_move_labelled<B>(
+    piece: &MoveGraph,
+    board: &B,
+    start_position: (i32, i32),
+    target_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> Option<LabelledMove>
+where
+    B: Board,
+{
+    let piece = piece.on_board(board);
+    let graph = OrientedGraph::new(&piece, invert_x, invert_y);
+    search(&graph, board, start_position, |head| {
+        head.current_position == target_position
+    })
+    .map(|head| LabelledMove {
+        path: Vec::<(i32, i32)>::from(Trace::Node(head.current_position, head.trace)),
+        labels: Vec::<usize>::from((*head.labels).clone())
+            .into_iter()
+            .map(|label| piece.labels()[label].clone())
+            .collect(),
+    })
+}
+
+/**
+Finds every position the piece could finish a move on, along with the path taken to get there (as returned by `check_move`).
+Like `check_move`, this does not test the finishing positions themselves; squares holding a friendly piece or lying out of bounds
+will be included if the piece could otherwise land there, so filter them as you would before calling `check_move`.
+*/
+pub fn generate_moves<B>(
+    piece: &MoveGraph,
+    board: &B,
+    start_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> Moves
+where
+    B: Board,
+{
+    let piece = piece.on_board(board);
+    let graph = OrientedGraph::new(&piece, invert_x, invert_y);
+    generate_oriented(&graph, board, start_position)
+}
+
+/**
+As `generate_moves`, but also returns the read-set of the generation: every position whose `tile_at` result it depended on.
+If none of these positions change, generating the moves again will give the same result.
+*/
+pub fn generate_moves_tracked<B>(
+    piece: &MoveGraph,
+    board: &B,
+    start_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> (Moves, HashSet<(i32, i32)>)
+where
+    B: Board,
+{
+    let tracker = ReadTracker::new(board);
+    let moves = generate_moves(piece, &tracker, start_position, invert_x, invert_y);
+    (moves, tracker.into_reads())
+}
+
+pub(crate) fn generate_oriented<B>(
+    graph: &OrientedGraph,
+    board: &B,
+    start_position: (i32, i32),
+) -> Moves
+where
+    B: Board,
+{
+    let mut moves = HashMap::new();
+    search(graph, board, start_position, |head| {
+        moves.entry(head.current_position).or_insert_with(|| {
+            Vec::<(i32, i32)>::from(Trace::Node(head.current_position, head.trace.clone()))
+        });
+        false
+    });
+    moves
+}
+
+/// A MoveGraph with the inversions applied to its jumps, flattened into a table indexed by node.
+/// This is what the search walks; it is cheap to build, but can be shared between searches of pieces of the same type.
+pub(crate) struct OrientedGraph {
+    pub(crate) head: NodeIndex<DefaultIx>,
+    outgoing: Vec<Vec<(NodeIndex<DefaultIx>, EdgeType)>>,
+    //whether a move may end at the node, i.e there are no further jumps or required choices to make
+    pub(crate) can_finish: Vec<bool>,
+}
+
+impl OrientedGraph {
+    pub(crate) fn new(piece: &MoveGraph, invert_x: bool, invert_y: bool) -> Self {
+        let mut outgoing = vec![Vec::new(); piece.graph.node_bound()];
+        let mut can_finish = vec![false; piece.graph.node_bound()];
+
+        for node in piece.graph.node_indices() {
+            outgoing[node.index()] = piece
+                .all_outgoing(node)
+                .map(|(n, e)| (n, e.weight().inverted(invert_x, invert_y)))
+                .collect();
+
+            can_finish[node.index()] = !outgoing[node.index()].iter().any(|(_, e)| match e {
+                EdgeType::Jump(_) => true,
+                EdgeType::DummyOptional => false,
+                EdgeType::DummyRequired => true,
+                EdgeType::Condition(_) => true,
+                EdgeType::Hurdle => true,
+                EdgeType::Guard(_, _) => true,
+                EdgeType::PhasingJump(_) => true,
+                EdgeType::Capture => true,
+                EdgeType::ShotStart => true,
+                EdgeType::ShotEnd => true,
+                EdgeType::Turn(_) => true,
+                EdgeType::Bounce(_, _, _) => true,
+                EdgeType::Label(_) => true,
+            });
+        }
+
+        OrientedGraph {
+            head: piece.head(),
+            outgoing,
+            can_finish,
+        }
+    }
+}
+
+/// Walks the moves of the piece, calling `on_finish` on every trace that has completed a move.
+/// Returns the first trace for which `on_finish` returns true, or None if the search is exhausted.
+fn search<B, F>(
+    graph: &OrientedGraph,
+    board: &B,
+    start_position: (i32, i32),
+    mut on_finish: F,
+) -> Option<MoveTrace<DefaultIx>>
+where
+    B: Board,
+    F: FnMut(&MoveTrace<DefaultIx>) -> bool,
+{
+    //depth-first search with a set storing the states we have visited before (and therefore don't need to visit again)
+    //a state is the position of the piece together with the point in the graph it has reached
 
     //We assume that board.tile_at() is cheap to call
     //TODO that might not be a good assumption, perhaps create a version of this algorithm that minimises such calls on the assumption it's expensive
 
-    let mut traces: Vec<MoveTrace<DefaultIx>> = vec![MoveTrace::<DefaultIx> {
-        current_move: piece.head(),
-        current_position: start_position,
-        trace: Rc::new(Trace::Root),
-    }];
+    let board = &Vacated {
+        board,
+        start_position,
+    };
+    let mut traces: Vec<MoveTrace<DefaultIx>> = vec![MoveTrace::root(graph.head, start_position)];
 
-    let mut visited: Vec<(i32, i32, NodeIndex<DefaultIx>)> = Vec::new();
+    let mut visited: HashSet<State> = HashSet::new();
 
     while let Some(head) = traces.pop() {
-        //println!("testing:{:?}",head.current_position);
-        if head.current_position == target_position {
-            //println!("Possible target!");
-            //we reached the target, check that we don't have further moves to make. If not, we've reached the target tile successfuly!
-            if !piece
-                .outgoing_edges(head.current_move)
-                .any(|e| match e.weight() {
-                    movespec::EdgeType::Jump(_) => true,
-                    movespec::EdgeType::DummyOptional => false,
-                    movespec::EdgeType::DummyRequired => true,
-                })
-            {
-                return Some(Vec::<(i32, i32)>::from(Trace::Node(
-                    head.current_position,
-                    head.trace,
-                )));
-            }
+        if graph.can_finish[head.current_move.index()] && on_finish(&head) {
+            return Some(head);
         }
 
         //test that this trace isn't in a loop
-        if visited
-            .iter()
-            .any(|(x, y, mov)| (*x, *y) == head.current_position && *mov == head.current_move)
-        {
+        if !visited.insert(head.state()) {
             //this trace has already been at this location at the same point in the graph!
             //this means it has looped once, so delete it
             continue;
         }
 
-        //if the next position is impassable, then we cannot continue on this trace; this is not a valid position to be in
-        //unless this position is the target position; which it isn't, otherwise we would have retarned in the previous block
-        //TODO don't like the fact that I have to collect the iterator halfway through
-        let next_moves = {
-            if board.tile_at(head.current_position) == TileState::Impassable {
-                //however, it is entirely possible that we are here but there are required dummy nodes.
-                //In which case, we can still continue on dummy nodes, but cannot on non-dummy nodes
-
-                piece
-                    .all_outgoing(head.current_move)
-                    .filter(|(_, e)| match e.weight() {
-                        movespec::EdgeType::Jump(_) => false,
-                        movespec::EdgeType::DummyOptional => true,
-                        movespec::EdgeType::DummyRequired => true,
-                    })
-                    .collect::<Vec<(
-                        NodeIndex<DefaultIx>,
-                        EdgeReference<movespec::EdgeType, DefaultIx>,
-                    )>>()
-            } else {
-                piece.all_outgoing(head.current_move).collect::<Vec<(
-                    NodeIndex<DefaultIx>,
-                    EdgeReference<movespec::EdgeType, DefaultIx>,
-                )>>()
+        step(graph, board, &head, &mut traces);
+    }
+
+    //the search is exhausted
+    None
+}
+
+/// Pushes the traces that follow on from head, by taking each outgoing edge of its node
+pub(crate) fn step<B>(
+    graph: &OrientedGraph,
+    board: &B,
+    head: &MoveTrace<DefaultIx>,
+    traces: &mut Vec<MoveTrace<DefaultIx>>,
+) where
+    B: Board,
+{
+    let tile = board.tile_at(head.current_position);
+
+    //if the current position is not empty, then we cannot jump away from it; this is not a valid position to be in
+    //we can still follow dummy edges however, as they don't require the piece to move
+    //a trace that hasn't moved yet is never blocked, as the piece itself is what occupies its tile, and neither is a piece the piece is passing through
+    //the searches vacate the start position anyway, but shots searched on their own start from wherever the piece is
+    let blocked = !matches!(*head.trace, Trace::Root)
+        && tile != TileState::Empty
+        && !(head.passing && TileCondition::Occupied.holds(tile));
+
+    for (n, e) in &graph.outgoing[head.current_move.index()] {
+        let j: Jump = match e {
+            EdgeType::Jump(j) | EdgeType::PhasingJump(j) => *j,
+            EdgeType::Turn(eighths) => match head.heading {
+                Some((x, y)) => turn(Jump { x, y }, *eighths),
+                //there is no leg to turn from yet
+                None => continue,
+            },
+            EdgeType::DummyOptional | EdgeType::DummyRequired => {
+                traces.push(head.follow(*n));
+                continue;
             }
-        }
-        .iter()
-        .map(|(n, e)| {
-            let mut j: Jump = match e.weight() {
-                movespec::EdgeType::Jump(j) => Jump { x: j.x, y: j.y },
-                movespec::EdgeType::DummyOptional | movespec::EdgeType::DummyRequired => {
-                    return MoveTrace {
-                        current_move: *n,
-                        current_position: head.current_position,
-                        trace: head.trace.clone(),
-                    };
+            EdgeType::Label(label) => {
+                traces.push(MoveTrace {
+                    labels: Rc::new(Trace::Node(*label, head.labels.clone())),
+                    ..head.follow(*n)
+                });
+                continue;
+            }
+            EdgeType::Condition(c) => {
+                //conditions don't move the piece either, but can only be passed if the tile it is on meets them
+                if c.holds(tile) {
+                    traces.push(head.follow(*n));
                 }
-            };
-
-            if invert_x {
-                j.x = -j.x;
+                continue;
             }
-
-            if invert_y {
-                j.y = -j.y;
+            EdgeType::Hurdle => {
+                if TileCondition::Occupied.holds(tile) {
+                    traces.push(MoveTrace {
+                        passing: true,
+                        ..head.follow(*n)
+                    });
+                }
+                continue;
             }
-
-            let new_trace = Rc::new(Trace::Node(
-                (head.current_position.0, head.current_position.1),
-                head.trace.clone(),
-            ));
-
-            let new_position = (head.current_position.0 + j.x, head.current_position.1 + j.y);
-            MoveTrace {
-                current_move: *n,
-                current_position: new_position,
-                trace: new_trace,
+            EdgeType::Capture => {
+                //the enemy is taken, so the piece can carry on from its tile
+                if TileCondition::Enemy.holds(tile) {
+                    traces.push(MoveTrace {
+                        passing: true,
+                        captures: Rc::new(Trace::Node(
+                            head.current_position,
+                            head.captures.clone(),
+                        )),
+                        ..head.follow(*n)
+                    });
+                }
+                continue;
             }
-        })
-        .collect();
-
-        //eagerly follow Dummy edges. We need to do this, otherwise, we will prematurely stop following the trace due to the impassability check.
-        //Consider a choice that is the last in the move sequence, that reaches the target position, which is impassable.
-        //Becuase there is still a dummy edge ahead of it, it is not considered a finished move, so we don;t return true. Instead, we drop the trace, as we are on an impassable tile!
-        //So we must eagerly follow them.
-        let mut follow_up: Vec<MoveTrace<DefaultIx>> = next_moves;
-        //Follow up on dummy edges until there are no dummy edges left
-        loop {
-            let mut followed_up_on = false;
-            let follow_up_next: Vec<MoveTrace<DefaultIx>> = follow_up
-                .iter()
-                .flat_map(|mt| {
-                    let hd = mt.current_move;
-
-                    //if all outgoing edges are optional or non-dummy (or there are no outgoing edges), stay here. Otherwise, advance!
-
-                    if !piece.all_outgoing(hd).any(|(_, e)| match e.weight() {
-                        movespec::EdgeType::Jump(_) => true,
-                        movespec::EdgeType::DummyOptional => true,
-                        movespec::EdgeType::DummyRequired => false,
-                    }) {
-                        //there exist at least one non-optional egde. Therefore, we cannot stay here, so follow up required dummy edges
-                        piece
-                            .all_outgoing(hd)
-                            .map(|(n, e)| match e.weight() {
-                                movespec::EdgeType::DummyRequired => {
-                                    followed_up_on = true;
-                                    MoveTrace {
-                                        current_move: n,
-                                        current_position: mt.current_position,
-                                        trace: mt.trace.clone(),
-                                    }
-                                }
-
-                                _ => MoveTrace {
-                                    current_move: hd,
-                                    current_position: mt.current_position,
-                                    trace: mt.trace.clone(),
-                                },
-                            })
-                            .collect::<Vec<MoveTrace<DefaultIx>>>()
-                    } else {
-                        //all outgoing edges are optional or non-dummy, so just return self; we don't *have* to advance in any way, so return the current node
-                        vec![MoveTrace {
-                            current_move: hd,
-                            current_position: mt.current_position,
-                            trace: mt.trace.clone(),
-                        }]
+            EdgeType::Guard(offset, c) => {
+                let guarded = (
+                    head.current_position.0 + offset.x,
+                    head.current_position.1 + offset.y,
+                );
+                if c.holds(board.tile_at(guarded)) {
+                    traces.push(head.follow(*n));
+                }
+                continue;
+            }
+            EdgeType::Bounce(step, flip_x, flip_y) => {
+                if leaves_board(board, head.current_position, *step) == Some((*flip_x, *flip_y)) {
+                    traces.push(head.follow(*n));
+                }
+                continue;
+            }
+            EdgeType::ShotStart => {
+                //shots cannot be nested
+                //the shot makes its own legs, so its relative steps are not turned from the piece's
+                if head.shot.is_none() {
+                    traces.push(MoveTrace {
+                        shot: Some(Rc::new(Shot {
+                            origin: head.current_position,
+                            trace: head.trace.clone(),
+                            passing: head.passing,
+                            heading: head.heading,
+                        })),
+                        heading: None,
+                        ..head.follow(*n)
+                    });
+                }
+                continue;
+            }
+            EdgeType::ShotEnd => {
+                //the shot captures the enemy it reached, and the piece is where it was when it shot
+                if let Some(shot) = &head.shot {
+                    if TileCondition::Enemy.holds(tile) {
+                        traces.push(MoveTrace {
+                            current_move: *n,
+                            current_position: shot.origin,
+                            trace: shot.trace.clone(),
+                            passing: shot.passing,
+                            captures: Rc::new(Trace::Node(
+                                head.current_position,
+                                head.captures.clone(),
+                            )),
+                            labels: head.labels.clone(),
+                            shot: None,
+                            heading: shot.heading,
+                        });
                     }
-                })
-                .collect();
-
-            if !followed_up_on {
-                break;
+                }
+                continue;
             }
+        };
 
-            follow_up = follow_up_next;
+        if blocked {
+            continue;
         }
 
-        visited.push((
-            head.current_position.0,
-            head.current_position.1,
-            head.current_move,
-        ));
-        traces.append(&mut follow_up);
+        let new_trace = Rc::new(Trace::Node(head.current_position, head.trace.clone()));
+
+        let new_position = (head.current_position.0 + j.x, head.current_position.1 + j.y);
+        traces.push(MoveTrace {
+            current_move: *n,
+            current_position: new_position,
+            trace: new_trace,
+            //the landings of a phasing move may be on pieces
+            passing: matches!(e, EdgeType::PhasingJump(_)),
+            captures: head.captures.clone(),
+            labels: head.labels.clone(),
+            shot: head.shot.clone(),
+            heading: Some((j.x, j.y)),
+        });
     }
-
-    //no trace found a path to the target, no path could exist!
-    None
 }
 
 #[cfg(test)]
@@ -272,7 +624,12 @@ mod tests {
 
     use std::vec;
 
-    use crate::{check_move, movespec::MoveGraph, MoveCompact};
+    use std::collections::HashMap;
+
+    use crate::{
+        check_move, check_move_captures, check_move_labelled, generate_moves, movespec::MoveGraph,
+        CapturingMove, InstantiationError, MoveCompact,
+    };
 
     struct TestBoard {
         x_max: i32,
@@ -616,4 +973,805 @@ mod tests {
         assert!(check_move(piece, board, start_position, (6, 2), false, true).is_none());
         assert!(check_move(piece, board, start_position, (5, 2), false, true).is_none());
     }
+
+    struct PiecesTestBoard {
+        size: i32,
+        friends: Vec<(i32, i32)>,
+        enemies: Vec<(i32, i32)>,
+    }
+
+    impl crate::Board for PiecesTestBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            if position.0 < 0
+                || position.0 >= self.size
+                || position.1 < 0
+                || position.1 >= self.size
+            {
+                crate::TileState::Impassable
+            } else if self.friends.contains(&position) {
+                crate::TileState::Friendly
+            } else if self.enemies.contains(&position) {
+                crate::TileState::Enemy
+            } else {
+                crate::TileState::Empty
+            }
+        }
+    }
+
+    /// Every position the piece can finish a move on from the start, in order
+    fn finishing_positions<B: crate::Board>(
+        piece: &MoveGraph,
+        board: &B,
+        start: (i32, i32),
+        invert_x: bool,
+    ) -> Vec<(i32, i32)> {
+        let mut moves: Vec<(i32, i32)> = generate_moves(piece, board, start, invert_x, false)
+            .into_keys()
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    /// As `finishing_positions`, for the piece with the spec
+    fn spec_moves<B: crate::Board>(spec: &str, board: &B, start: (i32, i32)) -> Vec<(i32, i32)> {
+        finishing_positions(
+            &MoveGraph::from(spec.parse::<MoveCompact>().unwrap()),
+            board,
+            start,
+            false,
+        )
+    }
+
+    #[test]
+    fn pawn() {
+        let piece = &MoveGraph::from(("{[0,1]~,[1,1]|!}").parse::<MoveCompact>().unwrap());
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 2)],
+            enemies: vec![(2, 4), (4, 4), (5, 5)],
+        };
+
+        let valid = |start: (i32, i32), invert_y: bool| {
+            let points_r = (0..8).collect::<Vec<i32>>();
+            points_r
+                .iter()
+                .flat_map(|x| points_r.iter().map(|y| (*x, *y)))
+                .filter(|p| check_move(piece, board, start, *p, false, invert_y).is_some())
+                .collect::<Vec<(i32, i32)>>()
+        };
+
+        //can only capture diagonally, and only move straight
+        assert_eq!(valid((3, 3), false), vec![(2, 4), (3, 4), (4, 4)]);
+        //blocked by an enemy in front, with nothing to capture
+        assert_eq!(valid((5, 4), false), vec![]);
+        //can't move onto a friendly piece either
+        assert_eq!(valid((3, 3), true), vec![]);
+        assert_eq!(valid((1, 5), true), vec![(1, 4), (2, 4)]);
+    }
+
+    #[test]
+    fn start_position() {
+        let rook = &MoveGraph::from("[1,0]^*|-/".parse::<MoveCompact>().unwrap());
+
+        //the rook is on the board it is moving on, which doesn't stop it leaving its own tile
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 3)],
+            enemies: vec![],
+        };
+        assert_eq!(
+            check_move(rook, board, (3, 3), (3, 5), false, false),
+            Some(vec![(3, 3), (3, 4), (3, 5)])
+        );
+
+        //any other piece is still in the way
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 3), (3, 4)],
+            enemies: vec![],
+        };
+        assert_eq!(check_move(rook, board, (3, 3), (3, 5), false, false), None);
+        assert!(check_move(rook, board, (3, 3), (5, 3), false, false).is_some());
+
+        //the piece is not in its own way when it passes back over its start
+        let board = &SizedTestBoard(PiecesTestBoard {
+            size: 8,
+            friends: vec![(5, 3)],
+            enemies: vec![],
+        });
+        let there_and_back =
+            &MoveGraph::from("[1,0]*[-1,0]*[-1,0]".parse::<MoveCompact>().unwrap());
+        assert_eq!(
+            check_move(there_and_back, board, (5, 3), (4, 3), false, false),
+            Some(vec![(5, 3), (6, 3), (5, 3), (4, 3)])
+        );
+        let bouncing = &MoveGraph::from("[1,0]^*%".parse::<MoveCompact>().unwrap());
+        assert_eq!(
+            check_move(bouncing, board, (5, 3), (3, 3), false, false),
+            Some(vec![(5, 3), (6, 3), (7, 3), (6, 3), (5, 3), (4, 3), (3, 3)])
+        );
+        assert!(finishing_positions(bouncing, board, (5, 3), false).contains(&(0, 3)));
+        //but it is still in the way of any other piece
+        assert_eq!(
+            check_move(there_and_back, board, (4, 3), (3, 3), false, false),
+            None
+        );
+    }
+
+    #[test]
+    fn capture_only_rider() {
+        //a bishop that can only capture, and a rook that can only move
+        let piece = &MoveGraph::from(("{[1,1]^*|-!,[1,0]^*|-/~}").parse::<MoveCompact>().unwrap());
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(1, 1)],
+            enemies: vec![(5, 5), (6, 0), (3, 6)],
+        };
+
+        let points_r = (0..8).collect::<Vec<i32>>();
+        let valids: Vec<(i32, i32)> = points_r
+            .iter()
+            .flat_map(|x| points_r.iter().map(|y| (*x, *y)))
+            .filter(|p| check_move(piece, board, (3, 3), *p, false, false).is_some())
+            .filter(|p| p.0 != 3 && p.1 != 3)
+            .collect();
+
+        assert_eq!(valids, vec![(5, 5), (6, 0)]);
+        assert!(check_move(piece, board, (3, 3), (3, 5), false, false).is_some());
+        assert!(check_move(piece, board, (3, 3), (3, 6), false, false).is_none());
+        assert!(check_move(piece, board, (3, 3), (3, 7), false, false).is_none());
+    }
+
+    #[test]
+    fn hoppers() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 5), (5, 3), (5, 5)],
+            enemies: vec![(3, 7), (1, 3), (0, 3)],
+        };
+        let valid = |spec: &str| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            let points_r = (0..8).collect::<Vec<i32>>();
+            points_r
+                .iter()
+                .flat_map(|x| points_r.iter().map(|y| (*x, *y)))
+                .filter(|p| check_move(piece, board, (3, 3), *p, false, false).is_some())
+                .collect::<Vec<(i32, i32)>>()
+        };
+
+        //moves like a rook, but captures by jumping over exactly one piece; the edge of the board is not a hurdle
+        assert_eq!(
+            valid("{[1,0]^*|-/~,([1,0]^*:*[1,0]^*!)|-/}"),
+            vec![
+                (0, 3),
+                (2, 3),
+                (3, 0),
+                (3, 1),
+                (3, 2),
+                (3, 4),
+                (3, 7),
+                (4, 3)
+            ]
+        );
+        //lands directly behind the first piece in each direction
+        assert_eq!(
+            valid("{([1,0]^*:*[1,0])|-/,([1,1]^*:*[1,1])|-}"),
+            vec![(0, 3), (3, 6), (6, 3), (6, 6)]
+        );
+        //lands anywhere behind the first piece, but cannot pass a second one
+        assert_eq!(
+            valid("([1,0]^*:*[1,0]^*)|-/"),
+            vec![(0, 3), (3, 6), (3, 7), (6, 3), (7, 3)]
+        );
+    }
+
+    #[test]
+    fn lame_leapers() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(4, 5), (3, 5)],
+            enemies: vec![(3, 4), (5, 3)],
+        };
+        let valid = |spec: &str, invert_y: bool| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            let points_r = (0..8).collect::<Vec<i32>>();
+            points_r
+                .iter()
+                .flat_map(|x| points_r.iter().map(|y| (*x, *y)))
+                .filter(|p| check_move(piece, board, (4, 4), *p, false, invert_y).is_some())
+                .collect::<Vec<(i32, i32)>>()
+        };
+
+        //the xiangqi horse is blocked by a piece orthogonally next to it, in the direction it leaps furthest
+        assert_eq!(
+            valid("(?[0,1]*[1,2])|-/", false),
+            vec![(3, 2), (5, 2), (6, 3), (6, 5)]
+        );
+        //the elephant by a piece on the diagonal it leaps over
+        assert_eq!(valid("(?[1,1]*[2,2])|-", false), vec![(2, 2), (6, 6)]);
+        //guards are inverted along with the jumps
+        assert_eq!(valid("?[0,1]*[0,2]", false), vec![]);
+        assert_eq!(valid("?[0,1]*[0,2]", true), vec![(4, 2)]);
+        //guards can test for pieces too
+        assert_eq!(
+            valid("{?:[0,1]*[1,0],?![-1,0]*[0,-1]}", false),
+            vec![(4, 3), (5, 4)]
+        );
+    }
+
+    #[test]
+    fn phasing() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(2, 1), (3, 1)],
+            enemies: vec![(1, 3)],
+        };
+        let check = |spec: &str, target: (i32, i32)| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            check_move(piece, board, (1, 1), target, false, false)
+        };
+
+        assert_eq!(
+            check("[1,0]^3=", (4, 1)),
+            Some(vec![(1, 1), (2, 1), (3, 1), (4, 1)])
+        );
+        assert_eq!(check("[1,0]^3", (4, 1)), None);
+        //only the landings of the phasing part of a sequence may be on pieces
+        assert!(check("[1,0]=*[0,1]", (2, 2)).is_some());
+        assert!(check("[1,0]*[0,1]", (2, 2)).is_none());
+        assert!(check("[1,0]*[1,0]=*[0,1]", (3, 2)).is_none());
+
+        //passes through every piece, but not off the board
+        let ghost = &MoveGraph::from("([1,0]^*=)|-/".parse::<MoveCompact>().unwrap());
+        let moves = generate_moves(ghost, board, (1, 1), false, false);
+        //every square in its row and column, and the ones just beyond the edges
+        assert_eq!(moves.len(), 18);
+        assert!(moves.contains_key(&(1, 7)));
+        assert!(moves.contains_key(&(7, 1)));
+        assert!(moves.contains_key(&(1, -1)));
+    }
+
+    #[test]
+    fn locusts() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(0, 2)],
+            enemies: vec![(2, 2), (4, 4), (2, 1)],
+        };
+        let check = |spec: &str, target: (i32, i32)| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            check_move_captures(piece, board, (1, 1), target, false, false)
+        };
+
+        //a checkers man, which can chain its captures
+        let man = "(([1,1]+*[1,1]~)|)^*";
+        assert_eq!(
+            check(man, (5, 5)),
+            Some(CapturingMove {
+                path: vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)],
+                captures: vec![(2, 2), (4, 4)],
+            })
+        );
+        assert_eq!(check(man, (3, 3)).unwrap().captures, vec![(2, 2)]);
+        assert_eq!(check(man, (6, 6)), None);
+
+        //the lion can capture a piece next to it without moving
+        assert_eq!(
+            check("([1,0]+*[-1,0])|", (1, 1)),
+            Some(CapturingMove {
+                path: vec![(1, 1), (2, 1), (1, 1)],
+                captures: vec![(2, 1)],
+            })
+        );
+        //it can't capture its own pieces
+        assert_eq!(check("[-1,1]+*[1,-1]", (1, 1)), None);
+
+        //a capture on the target position is only listed if it is marked
+        assert_eq!(check("[1,0]+", (2, 1)).unwrap().captures, vec![(2, 1)]);
+        assert_eq!(check("[1,0]", (2, 1)).unwrap().captures, vec![]);
+
+        //capture-only just tests the square, so the piece can't carry on from it, and nothing is listed
+        assert_eq!(check("[1,0]!", (2, 1)).unwrap().captures, vec![]);
+        assert_eq!(check("[1,1]!*[1,1]~", (3, 3)), None);
+        assert_eq!(check(&man.replace('+', "!"), (3, 3)), None);
+    }
+
+    #[test]
+    fn shooting() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(1, 3)],
+            enemies: vec![(3, 5), (6, 3), (0, 3), (4, 4)],
+        };
+        let check = |spec: &str, target: (i32, i32)| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            check_move_captures(piece, board, (3, 3), target, false, false)
+        };
+
+        //the piece stays put, and the square shot at is captured
+        assert_eq!(
+            check("[0,1]^[1..3]>", (3, 3)),
+            Some(CapturingMove {
+                path: vec![(3, 3)],
+                captures: vec![(3, 5)],
+            })
+        );
+        //shots are blocked like any other move, and must hit an enemy
+        assert_eq!(check("[-1,0]^[1..3]>", (3, 3)), None);
+        assert_eq!(check("[0,-1]^[1..3]>", (3, 3)), None);
+        //an archer can shoot and then move
+        assert_eq!(
+            check("[0,1]^2>*[1,0]", (4, 3)),
+            Some(CapturingMove {
+                path: vec![(3, 3), (4, 3)],
+                captures: vec![(3, 5)],
+            })
+        );
+
+        let archer = &MoveGraph::from(
+            "{[1,0]|-/~,([1,0]^[1..3]>)|-/}"
+                .parse::<MoveCompact>()
+                .unwrap(),
+        );
+        assert_eq!(
+            finishing_positions(archer, board, (3, 3), false),
+            vec![(2, 3), (3, 2), (3, 3), (3, 4), (4, 3)]
+        );
+    }
+
+    #[test]
+    fn zero_or_more() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(5, 5)],
+            enemies: vec![],
+        };
+        let griffon = &MoveGraph::from(
+            "{[1,1]*[1,0]^[0..*],[1,1]*[0,1]^[0..*]}|-"
+                .parse::<MoveCompact>()
+                .unwrap(),
+        );
+
+        assert_eq!(
+            check_move(griffon, board, (2, 2), (3, 3), false, false),
+            Some(vec![(2, 2), (3, 3)])
+        );
+        assert_eq!(
+            check_move(griffon, board, (2, 2), (6, 3), false, false),
+            Some(vec![(2, 2), (3, 3), (4, 3), (5, 3), (6, 3)])
+        );
+        assert!(check_move(griffon, board, (2, 2), (1, 7), false, false).is_some());
+        assert!(check_move(griffon, board, (2, 2), (3, 7), false, false).is_some());
+        //blocked by the piece on (5,5) after its diagonal step from (4,4)
+        assert!(check_move(griffon, board, (4, 4), (5, 6), false, false).is_none());
+        assert!(check_move(griffon, board, (2, 2), (4, 4), false, false).is_none());
+
+        //the empty move can be made on its own, too
+        let piece = &MoveGraph::from("[1,0]^[0..*]".parse::<MoveCompact>().unwrap());
+        assert_eq!(generate_moves(piece, board, (5, 4), false, false).len(), 4);
+    }
+
+    #[test]
+    fn symmetries() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(5, 4)],
+            enemies: vec![],
+        };
+        let moves = |spec: &str| spec_moves(spec, board, (3, 3));
+
+        assert_eq!(moves("[1,2]@8"), moves("[1,2]|-/"));
+        assert_eq!(moves("[1,0]^*@4"), moves("[1,0]^*|-/"));
+        assert_eq!(moves("([1,1]*[1,0]^*)@8"), moves("([1,1]*[1,0]^*)|-/"));
+        assert_eq!(moves("[1,2]@4"), vec![(1, 4), (2, 1), (4, 5), (5, 2)]);
+        assert_eq!(moves("[1,2]@2"), vec![(2, 1), (4, 5)]);
+        assert_eq!(moves("[1,2]\\"), vec![(1, 2), (4, 5)]);
+
+        //the images are not repeated, unlike with the mirrors
+        let wazir = MoveGraph::from("[1,0]@8".parse::<MoveCompact>().unwrap());
+        assert_eq!(wazir.graph.edge_count(), 4);
+    }
+
+    #[test]
+    fn jump_ranges() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(4, 3)],
+            enemies: vec![(3, 5)],
+        };
+        let moves = |spec: &str| spec_moves(spec, board, (3, 3));
+
+        assert_eq!(moves("[-1..1,-1..1]"), moves("{[1,0]|-/,[1,1]|-}"));
+        //each jump in the range is a leap on its own, so the friend at (4,3) doesn't block the ones past it
+        assert_eq!(
+            moves("[1..3,0]|"),
+            vec![(0, 3), (1, 3), (2, 3), (4, 3), (5, 3), (6, 3)]
+        );
+        assert_eq!(moves("[1..3,0]|"), moves("{[1,0],[2,0],[3,0]}|"));
+    }
+
+    #[test]
+    fn set_operations() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 6)],
+            enemies: vec![(6, 6)],
+        };
+        let moves = |spec: &str| spec_moves(spec, board, (3, 3));
+
+        //a queen, without the moves of a king
+        let queen = moves("{[1,0]^*,[1,1]^*}@8");
+        let king = moves("[-1..1,-1..1]");
+        assert_eq!(
+            moves("{[1,0]^*,[1,1]^*}@8&![-1..1,-1..1]"),
+            queen
+                .into_iter()
+                .filter(|m| !king.contains(m))
+                .collect::<Vec<_>>()
+        );
+
+        //a rook that can only move an odd number of squares
+        assert_eq!(
+            moves("[1,0]^*@4&!([1,0]^2)^*@4"),
+            vec![
+                (0, 3),
+                (2, 3),
+                (3, 0),
+                (3, 2),
+                (3, 4),
+                (3, 6),
+                (4, 3),
+                (6, 3),
+                (8, 3)
+            ]
+        );
+        assert_eq!(moves("[1,0]^*@4&[1,0]^[2..3]@4"), moves("[1,0]^[2..3]@4"));
+
+        //the sequences of jumps are compared, not the squares they reach
+        assert_eq!(moves("[1,1]^*@4&[1,2]^*@8"), vec![]);
+        assert_eq!(moves("[2,0]&[1,0]^2"), vec![]);
+        assert_eq!(moves("[1,0]^2&![2,0]"), vec![(5, 3)]);
+        assert_eq!(moves("[1,0]^2&![1,0]*[1,0]"), vec![]);
+
+        //and the tests made along the way are part of them, so they must match too
+        assert_eq!(moves("[1,0]~&![1,0]"), vec![(4, 3)]);
+        assert_eq!(moves("[1,0]~&[1,0]"), vec![]);
+        assert_eq!(moves("[1,0]~&[1,0]~"), vec![(4, 3)]);
+        assert_eq!(moves("([1,0]&![1,0])~"), vec![]);
+    }
+
+    #[test]
+    fn locked_exponents() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(5, 4)],
+            enemies: vec![(1, 1)],
+        };
+        let moves = |spec: &str| spec_moves(spec, board, (3, 3));
+
+        //a nightrider
+        assert_eq!(moves("([1,2]|-/)^!*"), moves("[1,2]^*|-/"));
+        assert_ne!(moves("([1,2]|-/)^!*"), moves("([1,2]|-/)^*"));
+        assert_eq!(moves("{[1,0]^*,[1,1]^*}@8"), moves("{[1,0],[1,1]}@8^!*"));
+        assert_eq!(moves("{[1,0],[0,1]}^!2"), vec![(3, 5), (5, 3)]);
+        assert_eq!(moves("{[1,0]*[0,1],[1,1]}^![1..2]"), vec![(4, 4), (5, 5)]);
+    }
+
+    #[test]
+    fn unordered_sequences() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(4, 3)],
+            enemies: vec![],
+        };
+        let moves = |spec: &str| spec_moves(spec, board, (3, 3));
+
+        assert_eq!(
+            moves("([1,0]<>[1,1])|-/"),
+            moves("{[1,0]*[1,1],[1,1]*[1,0]}|-/")
+        );
+        assert_eq!(
+            moves("[1,0]<>[0,1]<>[1,1]"),
+            moves("{[1,0]*[0,1]*[1,1],[1,0]*[1,1]*[0,1],[0,1]*[1,0]*[1,1],[0,1]*[1,1]*[1,0],[1,1]*[1,0]*[0,1],[1,1]*[0,1]*[1,0]}")
+        );
+        //the friend at (4,3) blocks stepping right first, but not stepping right last
+        assert_eq!(moves("[1,0]<>[0,1]"), vec![(4, 4)]);
+        assert_eq!(moves("[1,0]*[0,1]"), vec![]);
+        //the repetitions of a move are not mixed into the other moves
+        assert_eq!(
+            moves("[0,1]^*<>[-1,0]"),
+            vec![(2, 4), (2, 5), (2, 6), (2, 7), (2, 8)]
+        );
+    }
+
+    #[test]
+    fn relative_steps() {
+        let board = &PiecesTestBoard {
+            size: 12,
+            friends: vec![(6, 7)],
+            enemies: vec![],
+        };
+        let moves = |spec: &str, invert_x: bool| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            finishing_positions(piece, board, (6, 1), invert_x)
+        };
+
+        //an eighth of a turn takes a knight's leg to the next one around
+        assert_eq!(moves("[2,1]*[L]", false), vec![(9, 4)]);
+        assert_eq!(moves("[2,1]*[L]^2", false), vec![(8, 6)]);
+        assert_eq!(moves("[2,1]*[R]", false), vec![(10, 1)]);
+        //and a quarter of a turn is a rotation by 90 degrees
+        assert_eq!(moves("[2,1]*[LL]", false), vec![(7, 4)]);
+        assert_eq!(moves("[1,0]*[L]", false), vec![(8, 2)]);
+        assert_eq!(moves("[1,1]*[LLLL]", false), vec![(6, 1)]);
+
+        //a relative step needs a leg to turn from
+        assert_eq!(moves("[F]", false), vec![]);
+        assert_eq!(moves("[1,0]*[F]^[0..*]", false), moves("[1,0]^*", false));
+
+        //a rose, which curves around an octagon; it is blocked by the friend at (6,7)
+        assert_eq!(
+            moves("[2,1]*[L]^[0..6]", false),
+            vec![(6, 7), (8, 2), (8, 6), (9, 4)]
+        );
+        //mirrors and inversions turn the other way
+        assert_eq!(moves("([2,1]*[L])|", false), vec![(3, 4), (9, 4)]);
+        assert_eq!(moves("[2,1]*[L]", true), moves("[-2,1]*[R]", false));
+    }
+
+    /// A PiecesTestBoard that reports its size
+    struct SizedTestBoard(PiecesTestBoard);
+
+    impl crate::Board for SizedTestBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            self.0.tile_at(position)
+        }
+
+        fn dimensions(&self) -> Option<(i32, i32)> {
+            Some((self.0.size, self.0.size))
+        }
+    }
+
+    #[test]
+    fn board_relative_bounds() {
+        let empty = |size: i32| PiecesTestBoard {
+            size,
+            friends: vec![],
+            enemies: vec![],
+        };
+        let moves = |spec: &str, size: i32, sized: bool| match sized {
+            true => spec_moves(spec, &SizedTestBoard(empty(size)), (1, 1)),
+            false => spec_moves(spec, &empty(size), (1, 1)),
+        };
+
+        //the bounds are resolved against each board the piece is on
+        assert_eq!(
+            moves("[1,0]^[1..w/2]", 8, true),
+            moves("[1,0]^[1..4]", 8, true)
+        );
+        assert_eq!(
+            moves("[1,0]^[1..w/2]", 12, true),
+            moves("[1,0]^[1..6]", 12, true)
+        );
+        assert_eq!(
+            moves("[0,1]^[2..h]", 8, true),
+            moves("[0,1]^[2..*]", 8, true)
+        );
+        //without the size of the board, they can't be
+        assert_eq!(moves("[1,0]^[1..w/2]", 8, false), vec![]);
+        assert_eq!(moves("{[1,1],[1,0]^[1..w]}", 8, false), vec![(2, 2)]);
+
+        //the edge needs no size: these must reach it
+        assert_eq!(
+            moves("[1,0]@4^![e..e]", 8, false),
+            vec![(0, 1), (1, 0), (1, 7), (7, 1)]
+        );
+        //and this may stop anywhere up to it, like an unbounded rider
+        assert_eq!(moves("[1,0]^[1..e]", 5, false), moves("[1,0]^*", 5, false));
+        assert_eq!(moves("[0,1]^[e..3]", 5, false), vec![(1, 4)]);
+        assert_eq!(moves("[0,1]^[e..3]", 6, false), vec![]);
+        //a piece already on the edge has to move away from it, not stay where it is
+        assert_eq!(
+            spec_moves("[1,0]@4^![e..e]", &empty(8), (7, 3)),
+            vec![(0, 3), (7, 0), (7, 7)]
+        );
+        //a move that goes nowhere never reaches the edge
+        assert_eq!(moves("([1,0]*[-1,0])^[e..e]", 8, false), vec![]);
+        //nor does one that goes different distances
+        assert_eq!(moves("[1,0]^[1..2]^[e..e]", 8, false), vec![]);
+        assert_eq!(moves("([1,0]|-)^[e..e]", 8, false), vec![]);
+        assert_eq!(moves("([1,0]^2|-)^[e..e]", 8, false), vec![]);
+    }
+
+    #[test]
+    fn bouncing_riders() {
+        let board = &SizedTestBoard(PiecesTestBoard {
+            size: 8,
+            friends: vec![(1, 6)],
+            enemies: vec![],
+        });
+        let path = |spec: &str, target: (i32, i32)| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            check_move(piece, board, (5, 0), target, false, false)
+        };
+
+        //the bishop bounces off the right edge, and then the top one; the path goes through where it bounced
+        assert_eq!(
+            path("[1,1]^*%", (3, 6)),
+            Some(vec![(5, 0), (6, 1), (7, 2), (6, 3), (5, 4), (4, 5), (3, 6)])
+        );
+        assert!(path("[1,1]^*%", (0, 5)).is_none());
+        assert_eq!(
+            path("[1,1]^*%", (1, 6)).map(|p| p[p.len() - 2]),
+            Some((2, 7))
+        );
+        //the bounces can be limited
+        assert!(path("[1,1]^*%1", (3, 6)).is_some());
+        assert!(path("[1,1]^*%1", (1, 6)).is_none());
+        assert!(path("[1,1]^*%0", (6, 3)).is_none());
+        assert!(path("[1,1]^*%0", (7, 2)).is_some());
+        //a jump that stays on the board is never reflected
+        assert!(path("[1,1]^*%", (4, 1)).is_none());
+        //other moves can be bounced, and mirrored
+        assert!(path("[1,2]^*%1", (6, 6)).is_some());
+        assert!(path("[1,2]^*%1", (5, 4)).is_none());
+        assert!(path("[1,2]^*%2", (5, 4)).is_some());
+        assert!(path("[1,1]^*%|-", (0, 5)).is_some());
+
+        //a board that doesn't report its size has no edge to bounce off
+        let piece = &MoveGraph::from("[1,1]^*%".parse::<MoveCompact>().unwrap());
+        assert!(check_move(piece, &board.0, (5, 0), (6, 3), false, false).is_none());
+        assert!(check_move(piece, &board.0, (5, 0), (7, 2), false, false).is_some());
+    }
+
+    #[test]
+    fn named_moves() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![(3, 5)],
+            enemies: vec![(5, 3)],
+        };
+        let mut bindings = crate::Bindings::new();
+        bindings.insert("rook", "[1,0]^*|-/".parse::<MoveCompact>().unwrap());
+        bindings.insert("bishop", "[1,1]^*|-".parse::<MoveCompact>().unwrap());
+        let moves =
+            |mov: MoveCompact| finishing_positions(&MoveGraph::from(mov), board, (3, 3), false);
+
+        //a named move moves just as it would written out in place
+        for (named, inline) in [
+            ("{$rook,$bishop}", "{[1,0]^*|-/,[1,1]^*|-}"),
+            ("{$rook,[1,2]|-/}", "{[1,0]^*|-/,[1,2]|-/}"),
+            ("let $step = [1,1]; ($step*$rook)|-", "([1,1]*[1,0]^*|-/)|-"),
+        ] {
+            assert_eq!(
+                moves(MoveCompact::parse_with(named, &bindings).unwrap()),
+                moves(inline.parse::<MoveCompact>().unwrap()),
+                "{}",
+                named
+            );
+        }
+    }
+
+    #[test]
+    fn piece_families() {
+        let board = &PiecesTestBoard {
+            size: 12,
+            friends: vec![(7, 6)],
+            enemies: vec![],
+        };
+        let moves =
+            |mov: &MoveCompact| finishing_positions(&MoveGraph::from(mov), board, (6, 6), false);
+        let leaper = "[m,n]|-/".parse::<MoveCompact>().unwrap();
+        let stepper = "[1,0]^[1..k]@4".parse::<MoveCompact>().unwrap();
+        assert_eq!(
+            leaper.parameters().into_iter().collect::<Vec<_>>(),
+            vec!["m", "n"]
+        );
+
+        //instantiating a family gives the piece written out with the values
+        for (family, values, piece) in [
+            (&leaper, vec![("m", 1), ("n", 2)], "[1,2]|-/"),
+            (&leaper, vec![("m", 0), ("n", 3)], "[0,3]|-/"),
+            (&stepper, vec![("k", 3)], "[1,0]^[1..3]@4"),
+            (&stepper, vec![("k", 1)], "[1,0]@4"),
+        ] {
+            let instance = family.instantiate(&values.into_iter().collect()).unwrap();
+            let piece = piece.parse::<MoveCompact>().unwrap();
+            assert_eq!(moves(&instance), moves(&piece), "{}", piece.notation());
+            assert!(instance.parameters().is_empty());
+        }
+        assert_eq!(
+            "[m,-n]^k"
+                .parse::<MoveCompact>()
+                .unwrap()
+                .instantiate(&HashMap::from([("m", 2), ("n", 1), ("k", 2)]))
+                .unwrap()
+                .notation(),
+            "[2,-1]^2"
+        );
+
+        //a template can't be moved with until it is instantiated
+        assert_eq!(moves(&leaper), vec![]);
+
+        //and instantiating it must give a valid move
+        assert_eq!(
+            leaper.instantiate(&HashMap::from([("m", 0), ("n", 0)])),
+            Err(InstantiationError::NotAValidJump)
+        );
+        assert_eq!(
+            leaper.instantiate(&HashMap::from([("m", 1)])),
+            Err(InstantiationError::MissingParameter(String::from("n")))
+        );
+        assert_eq!(
+            stepper.instantiate(&HashMap::from([("k", 0)])),
+            Err(InstantiationError::UpperExpLessThanLower(1, 0))
+        );
+        assert_eq!(
+            stepper.instantiate(&HashMap::from([("k", -2)])),
+            Err(InstantiationError::NegativeExponent(String::from("k"), -2))
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let board = &PiecesTestBoard {
+            size: 8,
+            friends: vec![],
+            enemies: vec![(4, 4), (6, 3)],
+        };
+        let labels = |spec: &str, target: (i32, i32)| {
+            let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+            check_move_labelled(piece, board, (3, 3), target, false, false).map(|m| m.labels)
+        };
+
+        let pawn = "{[0,1]~,#double([0,1]^2~),#take([1,1]|!)}";
+        assert_eq!(labels(pawn, (3, 4)), Some(vec![]));
+        assert_eq!(labels(pawn, (3, 5)), Some(vec![String::from("double")]));
+        assert_eq!(labels(pawn, (4, 4)), Some(vec![String::from("take")]));
+        assert_eq!(labels(pawn, (2, 4)), None);
+
+        //every label crossed is reported, in the order it was crossed, as often as it was crossed
+        let names = |names: &[&str]| Some(names.iter().map(|n| n.to_string()).collect());
+        assert_eq!(
+            labels("#outer(#inner([1,0])^2*[0,1]*#inner([0,1]))", (5, 5)),
+            names(&["outer", "inner", "inner", "inner"])
+        );
+        assert_eq!(
+            labels("#ride([1,0])^*|-/", (3, 7)),
+            names(&["ride", "ride", "ride", "ride"])
+        );
+        assert_eq!(
+            labels("#a([-1,0])*#b([0,1])*#a([-1,0])", (1, 4)),
+            names(&["a", "b", "a"])
+        );
+        //the left of a set operation keeps its labels
+        assert_eq!(labels("#r([1,0]^*)&[1,0]^[1..2]", (5, 3)), names(&["r"]));
+        assert_eq!(labels("#r([1,0]^*)&[1,0]^[1..2]", (6, 3)), None);
+        //the path is the same as check_move's
+        let piece = &MoveGraph::from(pawn.parse::<MoveCompact>().unwrap());
+        assert_eq!(
+            check_move_labelled(piece, board, (3, 3), (3, 5), false, false).map(|m| m.path),
+            check_move(piece, board, (3, 3), (3, 5), false, false)
+        );
+        //shots are part of the move
+        assert_eq!(
+            labels("#shot([1,0]^*>)", (3, 3)),
+            Some(vec![String::from("shot")])
+        );
+
+        //labels don't change the moves a piece can make
+        for (labelled, plain) in [
+            (pawn, "{[0,1]~,[0,1]^2~,[1,1]|!}"),
+            ("#ride([1,0]|-)^!*", "([1,0]|-)^!*"),
+            ("(#a([1,1])*#b([1,0]^*))|-", "([1,1]*[1,0]^*)|-"),
+            ("#k([1,2]|-/)&![2,1]|-", "[1,2]|-/&![2,1]|-"),
+        ] {
+            assert_eq!(
+                spec_moves(labelled, board, (3, 3)),
+                spec_moves(plain, board, (3, 3)),
+                "{}",
+                labelled
+            );
+        }
+    }
 }
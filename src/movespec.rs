@@ -1,13 +1,25 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::iter::Zip;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
 use petgraph::graph::{DefaultIx, NodeIndex};
 use petgraph::stable_graph::EdgeReference;
-use petgraph::visit::{EdgeRef, IntoEdges, IntoNeighbors};
+use petgraph::visit::{Dfs, EdgeRef, IntoEdges, IntoNeighbors};
 use petgraph::EdgeDirection;
 
+use crate::compiler::compile_on_board;
 use crate::parser;
+pub use crate::parser::migrate_sequences;
+pub use crate::parser::Bindings;
+pub use crate::parser::Bound;
+pub use crate::parser::Component;
 pub use crate::parser::Jump;
 pub use crate::parser::Mod;
+pub use crate::parser::ParsingError;
+pub use crate::parser::Position;
+use crate::{Board, Pass, TileCondition};
+use thiserror::Error;
 
 //TODO implement equality such that two choice nodes that have thier choices in a different order, but the same choices, are equal.
 #[derive(Debug, PartialEq, Clone)]
@@ -15,46 +27,310 @@ pub enum MoveCompact {
     Jump(Jump),
     Choice(Vec<MoveCompact>),
     Sequence(Vec<MoveCompact>),
+    /// A sequence of the moves, made in any order
+    Unordered(Vec<MoveCompact>),
     Modded(Box<MoveCompact>, Mod),
+    /// Tests the tile at an offset from the piece, without moving it
+    Guard(Jump, TileCondition),
+    /// Jumps along the previous leg of the move, turned anticlockwise by this many eighths of a turn
+    Turn(u8),
+    /// The sequences of jumps that both moves can make
+    Intersection(Box<MoveCompact>, Box<MoveCompact>),
+    /// The sequences of jumps that the first move can make, but the second can't
+    Difference(Box<MoveCompact>, Box<MoveCompact>),
+    /// A move that was referred to by name, as `$name`
+    Named(String, Box<MoveCompact>),
+    /// A jump with parameters for one or both of its components. The move must be instantiated before it can make this jump
+    JumpTemplate(Component, Component),
+    /// A move tagged with a label, as `#label(move)`, which moves made with it report
+    Labelled(String, Box<MoveCompact>),
+}
+
+/// Why a move couldn't be instantiated with the values given for its parameters
+#[derive(Debug, PartialEq, Error)]
+pub enum InstantiationError {
+    #[error("No value was given for the parameter {0}")]
+    MissingParameter(String),
+    #[error("[0,0] is not a valid jump")]
+    NotAValidJump,
+    #[error("The exponent {0} can't be negative, but was given {1}")]
+    NegativeExponent(String, i32),
+    #[error("Upper bound ({1}) in exponent range is less than lower bound ({0})")]
+    UpperExpLessThanLower(usize, usize),
+}
+
+//a named move to write a let for, with the name and move it had, and those it is written with
+struct Definition<'a> {
+    name: &'a str,
+    mov: &'a MoveCompact,
+    written_name: String,
+    written: MoveCompact,
+}
+
+/// How `MoveCompact::notation_with` writes a move out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NotationOptions {
+    /// Whether named moves are written as `$name` references, with a `let` for each of them before the move.
+    /// Otherwise they are written inline, as if they had never been named.
+    /// If different moves have the same name, the later ones are written as `$name_2`, `$name_3` and so on
+    pub keep_references: bool,
+    /// Whether sequences are written with `.` rather than `*`, which can be confused with the infinite exponent
+    pub dot_sequences: bool,
 }
 
 impl MoveCompact {
-    pub(crate) fn notation(&self) -> String {
+    /// The move written out, with any named moves written inline
+    pub fn notation(&self) -> String {
+        self.notation_with(NotationOptions::default())
+    }
+
+    /// The move written out as the options ask
+    pub fn notation_with(&self, options: NotationOptions) -> String {
+        if !options.keep_references {
+            return self.expanded().written(options);
+        }
+        //the definitions go first, so that the move can be read back without the bindings it was read with
+        let mut definitions = Vec::new();
+        let mov = self.named_distinctly(&mut definitions);
+        definitions
+            .iter()
+            .map(|d| format!("let ${} = {}; ", d.written_name, d.written.written(options)))
+            .collect::<String>()
+            + &mov.written(options)
+    }
+
+    fn written(&self, options: NotationOptions) -> String {
         //TODO: not sure how efficient format!() is, or any of this function really
         match self {
             MoveCompact::Jump(j) => format!("[{},{}]", j.x, j.y),
-            MoveCompact::Choice(moves) => format!(
-                "{{{}}}",
-                moves
-                    .iter()
-                    .map(|x| x.notation())
-                    .collect::<Vec<String>>()
-                    .join(",")
+            MoveCompact::Guard(j, condition) => format!(
+                "?{}[{},{}]",
+                match condition {
+                    TileCondition::Empty => "",
+                    TileCondition::Occupied => ":",
+                    TileCondition::Enemy => "!",
+                    TileCondition::Impassable => "#",
+                },
+                j.x,
+                j.y
             ),
+            MoveCompact::Turn(eighths) => match eighths {
+                0 => String::from("[F]"),
+                1..=4 => format!("[{}]", "L".repeat(*eighths as usize)),
+                _ => format!("[{}]", "R".repeat(8 - *eighths as usize)),
+            },
+            MoveCompact::Choice(moves) => match jump_range_bounds(moves) {
+                Some((x, y)) => format!("[{},{}]", range_notation(x), range_notation(y)),
+                None => format!(
+                    "{{{}}}",
+                    moves
+                        .iter()
+                        .map(|x| x.written(options))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                ),
+            },
             MoveCompact::Sequence(moves) => moves
                 .iter()
-                .map(|x| x.notation())
+                .map(|x| match x {
+                    MoveCompact::Intersection(_, _) | MoveCompact::Difference(_, _) => {
+                        format!("({})", x.written(options))
+                    }
+                    _ => x.written(options),
+                })
+                .collect::<Vec<String>>()
+                .join(match options.dot_sequences {
+                    true => ".",
+                    false => "*",
+                }),
+            //a sequence, or a set operation, binds looser than <>, and so does a nested <> as it is read as one move
+            MoveCompact::Unordered(moves) => moves
+                .iter()
+                .map(|x| match x {
+                    MoveCompact::Sequence(_)
+                    | MoveCompact::Unordered(_)
+                    | MoveCompact::Intersection(_, _)
+                    | MoveCompact::Difference(_, _) => format!("({})", x.written(options)),
+                    _ => x.written(options),
+                })
                 .collect::<Vec<String>>()
-                .join("*"),
+                .join("<>"),
+            MoveCompact::Intersection(lhs, rhs) => {
+                format!(
+                    "{}&{}",
+                    lhs.written(options),
+                    rhs.set_operand_notation(options)
+                )
+            }
+            MoveCompact::Difference(lhs, rhs) => {
+                format!(
+                    "{}&!{}",
+                    lhs.written(options),
+                    rhs.set_operand_notation(options)
+                )
+            }
             MoveCompact::Modded(base, modifier) => {
-                let left: String = base.notation();
-                let mod_sequence = match modifier {
-                    Mod::DiagonalMirror => String::from("/"),
-                    Mod::HorizontalMirror => String::from("-"),
-                    Mod::VerticalMirror => String::from("|"),
-                    Mod::Exponentiate(num) => format!("^{}", num),
-                    Mod::ExponentiateRange(lower, upper) => {
-                        format!("^[{}..{}]", lower, upper)
-                    }
-                    Mod::ExponentiateInfinite(lower) => match lower {
-                        1 => String::from("^*"),
-                        lower => format!("^[{}..*]", lower),
-                    },
+                //a modifier binds tighter than a sequence, so a modded sequence must be bracketed
+                let left: String = match **base {
+                    MoveCompact::Sequence(_)
+                    | MoveCompact::Unordered(_)
+                    | MoveCompact::Intersection(_, _)
+                    | MoveCompact::Difference(_, _) => format!("({})", base.written(options)),
+                    _ => base.written(options),
                 };
+                let mod_sequence = mod_notation(modifier);
                 left + &mod_sequence
             }
+            MoveCompact::Named(name, mov) => match options.keep_references {
+                true => format!("${}", name),
+                false => mov.written(options),
+            },
+            MoveCompact::JumpTemplate(x, y) => {
+                format!("[{},{}]", component_notation(x), component_notation(y))
+            }
+            MoveCompact::Labelled(label, mov) => format!("#{}({})", label, mov.written(options)),
         }
     }
+
+    //set operations are read from left to right, so one on the right of another must be bracketed
+    fn set_operand_notation(&self, options: NotationOptions) -> String {
+        match self {
+            MoveCompact::Intersection(_, _) | MoveCompact::Difference(_, _) => {
+                format!("({})", self.written(options))
+            }
+            _ => self.written(options),
+        }
+    }
+}
+
+fn mod_notation(modifier: &Mod) -> String {
+    match modifier {
+        Mod::DiagonalMirror => String::from("/"),
+        Mod::HorizontalMirror => String::from("-"),
+        Mod::VerticalMirror => String::from("|"),
+        Mod::AntiDiagonalMirror => String::from("\\"),
+        Mod::HalfTurn => String::from("@2"),
+        Mod::QuarterTurns => String::from("@4"),
+        Mod::FullSymmetry => String::from("@8"),
+        Mod::Exponentiate(num) => format!("^{}", num),
+        Mod::ExponentiateParameter(name) => format!("^{}", name),
+        Mod::ExponentiateRange(lower, upper) => {
+            format!("^[{}..{}]", bound_notation(lower), bound_notation(upper))
+        }
+        Mod::ExponentiateInfinite(lower) => match lower {
+            1 => String::from("^*"),
+            lower => format!("^[{}..*]", lower),
+        },
+        Mod::MoveOnly => String::from("~"),
+        Mod::CaptureOnly => String::from("!"),
+        Mod::Capture => String::from("+"),
+        Mod::Hurdle => String::from(":"),
+        Mod::Phasing => String::from("="),
+        Mod::Shoot => String::from(">"),
+        Mod::Bounce(limit) => match limit {
+            Some(limit) => format!("%{}", limit),
+            None => String::from("%"),
+        },
+        Mod::Locked(exponent) => mod_notation(exponent).replacen('^', "^!", 1),
+    }
+}
+
+fn bound_notation(bound: &Bound) -> String {
+    let dimension = |name: &str, divisor: usize| match divisor {
+        1 => String::from(name),
+        divisor => format!("{}/{}", name, divisor),
+    };
+    match bound {
+        Bound::Exactly(n) => format!("{}", n),
+        Bound::Width(divisor) => dimension("w", *divisor),
+        Bound::Height(divisor) => dimension("h", *divisor),
+        Bound::Edge => String::from("e"),
+        Bound::Parameter(name) => name.clone(),
+    }
+}
+
+fn value(name: &str, values: &HashMap<&str, i32>) -> Result<i32, InstantiationError> {
+    values
+        .get(name)
+        .copied()
+        .ok_or_else(|| InstantiationError::MissingParameter(String::from(name)))
+}
+
+/// The modifier with the values put in for its parameters
+fn instantiate_mod(modifier: &Mod, values: &HashMap<&str, i32>) -> Result<Mod, InstantiationError> {
+    let exponent = |name: &str| {
+        let v = value(name, values)?;
+        usize::try_from(v).map_err(|_| InstantiationError::NegativeExponent(String::from(name), v))
+    };
+    let bound = |b: &Bound| match b {
+        Bound::Parameter(name) => exponent(name).map(Bound::Exactly),
+        b => Ok(b.clone()),
+    };
+    Ok(match modifier {
+        Mod::ExponentiateParameter(name) => Mod::Exponentiate(exponent(name)?),
+        Mod::ExponentiateRange(lower, upper) => match (bound(lower)?, bound(upper)?) {
+            (Bound::Exactly(l), Bound::Exactly(u)) if u < l => {
+                return Err(InstantiationError::UpperExpLessThanLower(l, u))
+            }
+            //a range of just one exponent can't be written as a range, so it is that exponent
+            (Bound::Exactly(l), Bound::Exactly(u)) if u == l => Mod::Exponentiate(l),
+            (l, u) => Mod::ExponentiateRange(l, u),
+        },
+        Mod::Locked(exponent) => Mod::Locked(Box::new(instantiate_mod(exponent, values)?)),
+        modifier => modifier.clone(),
+    })
+}
+
+fn component_notation(component: &Component) -> String {
+    match component {
+        Component::Value(v) => format!("{}", v),
+        Component::Parameter(name) => name.clone(),
+        Component::Negated(name) => format!("-{}", name),
+    }
+}
+
+/// Every jump in a range of jumps, `[x.0..x.1,y.0..y.1]`, in the order they are written out in;
+/// `[0,0]` is left out
+pub(crate) fn jump_range(x: (i32, i32), y: (i32, i32)) -> Vec<Jump> {
+    (x.0..=x.1)
+        .flat_map(|jx| (y.0..=y.1).map(move |jy| Jump { x: jx, y: jy }))
+        .filter(|j| j.x != 0 || j.y != 0)
+        .collect()
+}
+
+//the bounds of the range a choice was expanded from, if it is exactly the jumps of a range
+fn jump_range_bounds(moves: &[MoveCompact]) -> Option<((i32, i32), (i32, i32))> {
+    let jumps: Vec<Jump> = moves
+        .iter()
+        .map(|m| match m {
+            MoveCompact::Jump(j) => Some(*j),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if jumps.len() < 2 {
+        return None;
+    }
+    let x = (
+        jumps.iter().map(|j| j.x).min()?,
+        jumps.iter().map(|j| j.x).max()?,
+    );
+    let y = (
+        jumps.iter().map(|j| j.y).min()?,
+        jumps.iter().map(|j| j.y).max()?,
+    );
+    if jump_range(x, y) == jumps {
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+fn range_notation((lower, upper): (i32, i32)) -> String {
+    if lower == upper {
+        format!("{}", lower)
+    } else {
+        format!("{}..{}", lower, upper)
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -84,6 +360,13 @@ impl From<MoveCompact> for String {
     }
 }
 
+impl MoveCompact {
+    /// Parses the move, which may refer to the moves bound to names in bindings as well as to its own
+    pub fn parse_with(s: &str, bindings: &Bindings) -> Result<Self, parser::ParsingError> {
+        parser::parse_with_bindings(s, bindings)
+    }
+}
+
 impl std::str::FromStr for MoveCompact {
     type Err = parser::ParsingError;
 
@@ -92,10 +375,57 @@ impl std::str::FromStr for MoveCompact {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MoveGraph<Ix: petgraph::adj::IndexType = DefaultIx> {
     pub graph: petgraph::stable_graph::StableDiGraph<(), EdgeType, Ix>,
     head: NodeIndex<DefaultIx>,
+    //set if the move has exponents that depend on the size of the board
+    //the graph is then built again for each size of board it is searched on
+    board_relative: Option<BoardRelative>,
+    //the labels of the move, which the label edges index into
+    labels: Vec<String>,
+}
+
+/// The graphs built for each size of board, shared by every clone of the graph they were built for
+pub(crate) type Resolved<G> = Arc<Mutex<HashMap<Option<(i32, i32)>, Arc<G>>>>;
+
+/// The graph for a board of these dimensions, building it the first time they are asked for
+pub(crate) fn resolve<G>(
+    resolved: &Resolved<G>,
+    dimensions: Option<(i32, i32)>,
+    build: impl FnOnce() -> G,
+) -> Arc<G> {
+    //a panicking build leaves nothing half-inserted, so a poisoned lock is still usable
+    let mut resolved = resolved.lock().unwrap_or_else(|e| e.into_inner());
+    resolved
+        .entry(dimensions)
+        .or_insert_with(|| Arc::new(build()))
+        .clone()
+}
+
+#[derive(Debug, Clone)]
+struct BoardRelative {
+    input: MoveCompact,
+    //the passes the graph was compiled with, which are run again on each graph built from it
+    passes: Vec<Pass>,
+    resolved: Resolved<MoveGraph>,
+}
+
+/// A graph to search on a particular board: either the graph itself, or the one built for the size of the board
+pub(crate) enum OnBoard<'a, G> {
+    Borrowed(&'a G),
+    Resolved(Arc<G>),
+}
+
+impl<'a, G> Deref for OnBoard<'a, G> {
+    type Target = G;
+
+    fn deref(&self) -> &G {
+        match self {
+            OnBoard::Borrowed(g) => g,
+            OnBoard::Resolved(g) => g,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -103,19 +433,57 @@ pub enum EdgeType {
     Jump(Jump),
     DummyOptional,
     DummyRequired,
+    /// Does not move the piece, but can only be followed if the tile the piece is on meets the condition
+    Condition(TileCondition),
+    /// Does not move the piece, but can only be followed if the tile the piece is on is occupied by a piece.
+    /// The piece may then jump away from that tile, passing over the piece on it.
+    Hurdle,
+    /// Does not move the piece, but can only be followed if the tile at the offset from the piece meets the condition
+    Guard(Jump, TileCondition),
+    /// A jump that may land on a piece, and jump on from there, passing through it
+    PhasingJump(Jump),
+    /// Does not move the piece, but can only be followed if the tile the piece is on holds an enemy, which is captured.
+    /// The piece may then jump on from that tile.
+    Capture,
+    /// Starts a shot. The jumps up to the matching `ShotEnd` move the shot, rather than the piece.
+    ShotStart,
+    /// Ends a shot: can only be followed if the shot has reached an enemy, which is captured.
+    /// The piece is then back where it was when the shot started.
+    ShotEnd,
+    /// A jump along the previous leg of the move, turned anticlockwise by this many eighths of a turn.
+    /// Can't be followed before the move has made a leg.
+    Turn(u8),
+    /// Does not move the piece, but can only be followed if the step would take the piece off the board across exactly the flagged axes (x, then y).
+    /// The piece is then reflected in those axes. Can't be followed on a board that doesn't report its dimensions.
+    Bounce(Jump, bool, bool),
+    /// Does not move the piece, but marks that the move is making the part of the move with this label, indexing into `MoveGraph::labels`
+    Label(usize),
+}
+
+impl EdgeType {
+    /// The edge with the inversions applied to its offset, if it has one
+    pub(crate) fn inverted(&self, invert_x: bool, invert_y: bool) -> EdgeType {
+        let invert = |j: &Jump| Jump {
+            x: if invert_x { -j.x } else { j.x },
+            y: if invert_y { -j.y } else { j.y },
+        };
+        match self {
+            EdgeType::Jump(j) => EdgeType::Jump(invert(j)),
+            EdgeType::Guard(j, c) => EdgeType::Guard(invert(j), *c),
+            EdgeType::Bounce(j, flip_x, flip_y) => EdgeType::Bounce(invert(j), *flip_x, *flip_y),
+            EdgeType::PhasingJump(j) => EdgeType::PhasingJump(invert(j)),
+            //inverting one axis is a reflection, which turns the other way
+            EdgeType::Turn(eighths) if invert_x != invert_y => EdgeType::Turn((8 - eighths) % 8),
+            e => *e,
+        }
+    }
 }
 
 impl From<&MoveCompact> for MoveGraph {
     fn from(input: &MoveCompact) -> Self {
-        let mut r = MoveGraph {
-            graph: petgraph::stable_graph::StableDiGraph::<(), EdgeType, DefaultIx>::with_capacity(
-                0, 0,
-            ),
-            head: NodeIndex::<DefaultIx>::default(),
-        };
-        let (h, _) = r.build_from_node(input);
-        r.head = h;
+        let mut r = MoveGraph::build(input);
         r.deflate();
+        r.set_passes(&[Pass::Deflate]);
         r
     }
 }
@@ -137,12 +505,526 @@ impl MoveCompact {
             MoveCompact::Jump(j) => MoveCompact::Jump(f(j)),
             MoveCompact::Choice(c) => MoveCompact::Choice(c.iter().map(|x| x.map(f)).collect()),
             MoveCompact::Sequence(s) => MoveCompact::Sequence(s.iter().map(|x| x.map(f)).collect()),
+            MoveCompact::Unordered(u) => {
+                MoveCompact::Unordered(u.iter().map(|x| x.map(f)).collect())
+            }
             MoveCompact::Modded(mo, md) => MoveCompact::Modded(Box::new(mo.map(f)), md.clone()),
+            MoveCompact::Guard(j, c) => MoveCompact::Guard(f(j), *c),
+            MoveCompact::Turn(eighths) => {
+                //a reflection turns the other way
+                let (x, y) = (f(&Jump { x: 1, y: 0 }), f(&Jump { x: 0, y: 1 }));
+                match x.x * y.y - x.y * y.x < 0 {
+                    true => MoveCompact::Turn((8 - eighths) % 8),
+                    false => MoveCompact::Turn(*eighths),
+                }
+            }
+            MoveCompact::Intersection(lhs, rhs) => {
+                MoveCompact::Intersection(Box::new(lhs.map(f)), Box::new(rhs.map(f)))
+            }
+            MoveCompact::Difference(lhs, rhs) => {
+                MoveCompact::Difference(Box::new(lhs.map(f)), Box::new(rhs.map(f)))
+            }
+            //the mapped move is no longer the one the name refers to
+            MoveCompact::Named(_, mov) => mov.map(f),
+            MoveCompact::JumpTemplate(x, y) => {
+                //as for turns, f is taken to be a symmetry of the square, which moves each component onto an axis, possibly negated
+                let (a, b) = (f(&Jump { x: 1, y: 0 }), f(&Jump { x: 0, y: 1 }));
+                let scaled = |c: &Component, k: i32| match (k, c) {
+                    (1, c) => c.clone(),
+                    (_, Component::Value(v)) => Component::Value(-v),
+                    (_, Component::Parameter(name)) => Component::Negated(name.clone()),
+                    (_, Component::Negated(name)) => Component::Parameter(name.clone()),
+                };
+                let component = |from_x: i32, from_y: i32| match from_x {
+                    0 => scaled(y, from_y),
+                    _ => scaled(x, from_x),
+                };
+                MoveCompact::JumpTemplate(component(a.x, b.x), component(a.y, b.y))
+            }
+            MoveCompact::Labelled(label, mov) => {
+                MoveCompact::Labelled(label.clone(), Box::new(mov.map(f)))
+            }
+        }
+    }
+
+    /// The move with every named move in it written out in place
+    pub fn expanded(&self) -> MoveCompact {
+        let all = |moves: &Vec<MoveCompact>| moves.iter().map(|m| m.expanded()).collect();
+        match self {
+            MoveCompact::Jump(_)
+            | MoveCompact::Guard(_, _)
+            | MoveCompact::Turn(_)
+            | MoveCompact::JumpTemplate(_, _) => self.clone(),
+            MoveCompact::Choice(moves) => MoveCompact::Choice(all(moves)),
+            MoveCompact::Sequence(moves) => MoveCompact::Sequence(all(moves)),
+            MoveCompact::Unordered(moves) => MoveCompact::Unordered(all(moves)),
+            MoveCompact::Modded(mov, modifier) => {
+                MoveCompact::Modded(Box::new(mov.expanded()), modifier.clone())
+            }
+            MoveCompact::Intersection(lhs, rhs) => {
+                MoveCompact::Intersection(Box::new(lhs.expanded()), Box::new(rhs.expanded()))
+            }
+            MoveCompact::Difference(lhs, rhs) => {
+                MoveCompact::Difference(Box::new(lhs.expanded()), Box::new(rhs.expanded()))
+            }
+            MoveCompact::Named(_, mov) => mov.expanded(),
+            MoveCompact::Labelled(label, mov) => {
+                MoveCompact::Labelled(label.clone(), Box::new(mov.expanded()))
+            }
+        }
+    }
+
+    /// The names of the parameters of the move, which must be given values to instantiate it
+    pub fn parameters(&self) -> BTreeSet<String> {
+        let mut parameters = BTreeSet::new();
+        self.add_parameters(&mut parameters);
+        parameters
+    }
+
+    fn add_parameters(&self, parameters: &mut BTreeSet<String>) {
+        match self {
+            MoveCompact::Jump(_) | MoveCompact::Guard(_, _) | MoveCompact::Turn(_) => (),
+            MoveCompact::Choice(moves)
+            | MoveCompact::Sequence(moves)
+            | MoveCompact::Unordered(moves) => {
+                for m in moves {
+                    m.add_parameters(parameters);
+                }
+            }
+            MoveCompact::Modded(mov, modifier) => {
+                mov.add_parameters(parameters);
+                let mut modifier = modifier;
+                while let Mod::Locked(exponent) = modifier {
+                    modifier = exponent;
+                }
+                match modifier {
+                    Mod::ExponentiateParameter(name) => {
+                        parameters.insert(name.clone());
+                    }
+                    Mod::ExponentiateRange(lower, upper) => {
+                        for bound in [lower, upper] {
+                            if let Bound::Parameter(name) = bound {
+                                parameters.insert(name.clone());
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            MoveCompact::Intersection(lhs, rhs) | MoveCompact::Difference(lhs, rhs) => {
+                lhs.add_parameters(parameters);
+                rhs.add_parameters(parameters);
+            }
+            MoveCompact::Named(_, mov) | MoveCompact::Labelled(_, mov) => {
+                mov.add_parameters(parameters)
+            }
+            MoveCompact::JumpTemplate(x, y) => {
+                for component in [x, y] {
+                    if let Component::Parameter(name) | Component::Negated(name) = component {
+                        parameters.insert(name.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// The move with the values put in for its parameters.
+    /// Fails if a parameter has no value, or if a value would make a jump of `[0,0]`, a negative exponent or an empty range of exponents
+    pub fn instantiate(
+        &self,
+        values: &HashMap<&str, i32>,
+    ) -> Result<MoveCompact, InstantiationError> {
+        let all = |moves: &Vec<MoveCompact>| {
+            moves
+                .iter()
+                .map(|m| m.instantiate(values))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(match self {
+            MoveCompact::Jump(_) | MoveCompact::Guard(_, _) | MoveCompact::Turn(_) => self.clone(),
+            MoveCompact::Choice(moves) => MoveCompact::Choice(all(moves)?),
+            MoveCompact::Sequence(moves) => MoveCompact::Sequence(all(moves)?),
+            MoveCompact::Unordered(moves) => MoveCompact::Unordered(all(moves)?),
+            MoveCompact::Modded(mov, modifier) => MoveCompact::Modded(
+                Box::new(mov.instantiate(values)?),
+                instantiate_mod(modifier, values)?,
+            ),
+            MoveCompact::Intersection(lhs, rhs) => MoveCompact::Intersection(
+                Box::new(lhs.instantiate(values)?),
+                Box::new(rhs.instantiate(values)?),
+            ),
+            MoveCompact::Difference(lhs, rhs) => MoveCompact::Difference(
+                Box::new(lhs.instantiate(values)?),
+                Box::new(rhs.instantiate(values)?),
+            ),
+            MoveCompact::Named(name, mov) => {
+                MoveCompact::Named(name.clone(), Box::new(mov.instantiate(values)?))
+            }
+            MoveCompact::Labelled(label, mov) => {
+                MoveCompact::Labelled(label.clone(), Box::new(mov.instantiate(values)?))
+            }
+            MoveCompact::JumpTemplate(x, y) => {
+                let component = |c: &Component| match c {
+                    Component::Value(v) => Ok(*v),
+                    Component::Parameter(name) => value(name, values),
+                    Component::Negated(name) => value(name, values).map(|v| -v),
+                };
+                match (component(x)?, component(y)?) {
+                    (0, 0) => return Err(InstantiationError::NotAValidJump),
+                    (x, y) => MoveCompact::Jump(Jump { x, y }),
+                }
+            }
+        })
+    }
+
+    /**
+    The move with its named moves renamed where a different move already has the same name, which can happen when a
+    move bound by the caller was itself read with other bindings. The named moves are added to the definitions,
+    each after the ones it refers to itself.
+    */
+    fn named_distinctly<'a>(&'a self, definitions: &mut Vec<Definition<'a>>) -> MoveCompact {
+        let mut all = |moves: &'a Vec<MoveCompact>| {
+            moves
+                .iter()
+                .map(|m| m.named_distinctly(definitions))
+                .collect()
+        };
+        match self {
+            MoveCompact::Jump(_)
+            | MoveCompact::Guard(_, _)
+            | MoveCompact::Turn(_)
+            | MoveCompact::JumpTemplate(_, _) => self.clone(),
+            MoveCompact::Choice(moves) => MoveCompact::Choice(all(moves)),
+            MoveCompact::Sequence(moves) => MoveCompact::Sequence(all(moves)),
+            MoveCompact::Unordered(moves) => MoveCompact::Unordered(all(moves)),
+            MoveCompact::Modded(mov, modifier) => MoveCompact::Modded(
+                Box::new(mov.named_distinctly(definitions)),
+                modifier.clone(),
+            ),
+            MoveCompact::Labelled(label, mov) => {
+                MoveCompact::Labelled(label.clone(), Box::new(mov.named_distinctly(definitions)))
+            }
+            MoveCompact::Intersection(lhs, rhs) => MoveCompact::Intersection(
+                Box::new(lhs.named_distinctly(definitions)),
+                Box::new(rhs.named_distinctly(definitions)),
+            ),
+            MoveCompact::Difference(lhs, rhs) => MoveCompact::Difference(
+                Box::new(lhs.named_distinctly(definitions)),
+                Box::new(rhs.named_distinctly(definitions)),
+            ),
+            MoveCompact::Named(name, mov) => {
+                let definition = match definitions
+                    .iter()
+                    .position(|d| d.name == name && d.mov == &**mov)
+                {
+                    Some(i) => &definitions[i],
+                    None => {
+                        let written = mov.named_distinctly(definitions);
+                        let written_name = (1..)
+                            .map(|i| match i {
+                                1 => name.clone(),
+                                i => format!("{}_{}", name, i),
+                            })
+                            .find(|n| !definitions.iter().any(|d| d.written_name == *n))
+                            .unwrap();
+                        definitions.push(Definition {
+                            name,
+                            mov,
+                            written_name,
+                            written,
+                        });
+                        definitions.last().unwrap()
+                    }
+                };
+                MoveCompact::Named(
+                    definition.written_name.clone(),
+                    Box::new(definition.written.clone()),
+                )
+            }
+        }
+    }
+}
+
+/// The jump turned anticlockwise by eighths of a turn.
+/// An eighth of a turn moves the jump a quarter of the way along a side of the square of jumps with as long a longest component,
+/// so that a quarter turn is exactly a rotation by 90 degrees, and the knight's jumps are turned into each other in order
+pub(crate) fn turn(jump: Jump, eighths: u8) -> Jump {
+    let r = jump.x.abs().max(jump.y.abs());
+    let Jump { mut x, mut y } = jump;
+    for _ in 0..(eighths % 8) as i32 * r {
+        if x == r && y < r {
+            y += 1;
+        } else if y == r && x > -r {
+            x -= 1;
+        } else if x == -r && y > -r {
+            y -= 1;
+        } else {
+            x += 1;
+        }
+    }
+    Jump { x, y }
+}
+
+impl MoveCompact {
+    /// Whether the move has a label anywhere in it, including in the moves it refers to by name
+    pub(crate) fn has_labels(&self) -> bool {
+        match self {
+            MoveCompact::Jump(_)
+            | MoveCompact::Guard(_, _)
+            | MoveCompact::Turn(_)
+            | MoveCompact::JumpTemplate(_, _) => false,
+            MoveCompact::Choice(moves)
+            | MoveCompact::Sequence(moves)
+            | MoveCompact::Unordered(moves) => moves.iter().any(|m| m.has_labels()),
+            MoveCompact::Modded(mov, _) | MoveCompact::Named(_, mov) => mov.has_labels(),
+            MoveCompact::Intersection(lhs, rhs) | MoveCompact::Difference(lhs, rhs) => {
+                lhs.has_labels() || rhs.has_labels()
+            }
+            MoveCompact::Labelled(_, _) => true,
+        }
+    }
+
+    /// Whether the move has a relative step anywhere in it, including in the moves it refers to by name
+    pub(crate) fn has_turns(&self) -> bool {
+        match self {
+            MoveCompact::Jump(_) | MoveCompact::Guard(_, _) | MoveCompact::JumpTemplate(_, _) => {
+                false
+            }
+            MoveCompact::Turn(_) => true,
+            MoveCompact::Choice(moves)
+            | MoveCompact::Sequence(moves)
+            | MoveCompact::Unordered(moves) => moves.iter().any(|m| m.has_turns()),
+            MoveCompact::Modded(mov, _)
+            | MoveCompact::Named(_, mov)
+            | MoveCompact::Labelled(_, mov) => mov.has_turns(),
+            MoveCompact::Intersection(lhs, rhs) | MoveCompact::Difference(lhs, rhs) => {
+                lhs.has_turns() || rhs.has_turns()
+            }
+        }
+    }
+
+    /// Whether any of the exponents of the move depend on the width or height of the board
+    fn depends_on_dimensions(&self) -> bool {
+        let depends = |b: &Bound| matches!(b, Bound::Width(_) | Bound::Height(_));
+        match self {
+            MoveCompact::Jump(_)
+            | MoveCompact::Guard(_, _)
+            | MoveCompact::Turn(_)
+            | MoveCompact::JumpTemplate(_, _) => false,
+            MoveCompact::Choice(moves)
+            | MoveCompact::Sequence(moves)
+            | MoveCompact::Unordered(moves) => moves.iter().any(|m| m.depends_on_dimensions()),
+            MoveCompact::Modded(mov, modifier) => {
+                mov.depends_on_dimensions()
+                    || match modifier {
+                        Mod::ExponentiateRange(min, max) => depends(min) || depends(max),
+                        Mod::Locked(exponent) => match &**exponent {
+                            Mod::ExponentiateRange(min, max) => depends(min) || depends(max),
+                            _ => false,
+                        },
+                        _ => false,
+                    }
+            }
+            MoveCompact::Intersection(lhs, rhs) | MoveCompact::Difference(lhs, rhs) => {
+                lhs.depends_on_dimensions() || rhs.depends_on_dimensions()
+            }
+            MoveCompact::Named(_, mov) | MoveCompact::Labelled(_, mov) => {
+                mov.depends_on_dimensions()
+            }
+        }
+    }
+
+    /// The move with the width and height of the board put into its exponents, if they are known
+    pub(crate) fn on_board(&self, dimensions: Option<(i32, i32)>) -> MoveCompact {
+        let resolve = |bound: &Bound| match (bound, dimensions) {
+            (Bound::Width(divisor), Some((width, _))) => {
+                Bound::Exactly(width.max(0) as usize / divisor)
+            }
+            (Bound::Height(divisor), Some((_, height))) => {
+                Bound::Exactly(height.max(0) as usize / divisor)
+            }
+            (bound, _) => bound.clone(),
+        };
+        let resolve_mod = |modifier: &Mod| match modifier {
+            Mod::ExponentiateRange(min, max) => Mod::ExponentiateRange(resolve(min), resolve(max)),
+            Mod::Locked(exponent) => match &**exponent {
+                Mod::ExponentiateRange(min, max) => {
+                    Mod::Locked(Box::new(Mod::ExponentiateRange(resolve(min), resolve(max))))
+                }
+                _ => modifier.clone(),
+            },
+            _ => modifier.clone(),
+        };
+        let all = |moves: &Vec<MoveCompact>| moves.iter().map(|m| m.on_board(dimensions)).collect();
+        match self {
+            MoveCompact::Jump(_)
+            | MoveCompact::Guard(_, _)
+            | MoveCompact::Turn(_)
+            | MoveCompact::JumpTemplate(_, _) => self.clone(),
+            MoveCompact::Choice(moves) => MoveCompact::Choice(all(moves)),
+            MoveCompact::Sequence(moves) => MoveCompact::Sequence(all(moves)),
+            MoveCompact::Unordered(moves) => MoveCompact::Unordered(all(moves)),
+            MoveCompact::Modded(mov, modifier) => {
+                MoveCompact::Modded(Box::new(mov.on_board(dimensions)), resolve_mod(modifier))
+            }
+            MoveCompact::Intersection(lhs, rhs) => MoveCompact::Intersection(
+                Box::new(lhs.on_board(dimensions)),
+                Box::new(rhs.on_board(dimensions)),
+            ),
+            MoveCompact::Difference(lhs, rhs) => MoveCompact::Difference(
+                Box::new(lhs.on_board(dimensions)),
+                Box::new(rhs.on_board(dimensions)),
+            ),
+            MoveCompact::Named(name, mov) => {
+                MoveCompact::Named(name.clone(), Box::new(mov.on_board(dimensions)))
+            }
+            MoveCompact::Labelled(label, mov) => {
+                MoveCompact::Labelled(label.clone(), Box::new(mov.on_board(dimensions)))
+            }
+        }
+    }
+
+    /// How far the move takes the piece, if it always takes it the same distance
+    fn displacement(&self) -> Option<Jump> {
+        let sum = |moves: &Vec<MoveCompact>| {
+            moves.iter().try_fold(Jump { x: 0, y: 0 }, |acc, m| {
+                m.displacement().map(|d| Jump {
+                    x: acc.x + d.x,
+                    y: acc.y + d.y,
+                })
+            })
+        };
+        let same = |moves: &Vec<MoveCompact>| {
+            let first = moves.first()?.displacement()?;
+            moves
+                .iter()
+                .all(|m| m.displacement() == Some(first))
+                .then_some(first)
+        };
+        match self {
+            MoveCompact::Jump(j) => Some(*j),
+            MoveCompact::Guard(_, _) => Some(Jump { x: 0, y: 0 }),
+            MoveCompact::Turn(_) | MoveCompact::JumpTemplate(_, _) => None,
+            MoveCompact::Choice(moves) => same(moves),
+            MoveCompact::Sequence(moves) | MoveCompact::Unordered(moves) => sum(moves),
+            MoveCompact::Modded(mov, modifier) => match modifier {
+                Mod::Exponentiate(n) => mov.displacement().map(|d| Jump {
+                    x: d.x * *n as i32,
+                    y: d.y * *n as i32,
+                }),
+                Mod::Locked(exponent) => match &**exponent {
+                    Mod::Exponentiate(n) => mov.displacement().map(|d| Jump {
+                        x: d.x * *n as i32,
+                        y: d.y * *n as i32,
+                    }),
+                    _ => None,
+                },
+                Mod::MoveOnly | Mod::CaptureOnly | Mod::Capture | Mod::Hurdle | Mod::Phasing => {
+                    mov.displacement()
+                }
+                Mod::Shoot => Some(Jump { x: 0, y: 0 }),
+                Mod::Bounce(_) => None,
+                //the mirrors and symmetries are choices; the ranges and infinite exponents go different distances
+                _ => symmetric_choice(mov, modifier).and_then(|choice| choice.displacement()),
+            },
+            //every sequence of jumps of these is one of the left move's
+            MoveCompact::Intersection(lhs, _) | MoveCompact::Difference(lhs, _) => {
+                lhs.displacement()
+            }
+            MoveCompact::Named(_, mov) | MoveCompact::Labelled(_, mov) => mov.displacement(),
+        }
+    }
+}
+
+/// The symmetries of the square, ordered so that the first two make up the half turn, and the first four the quarter turns
+const SYMMETRIES: [fn(&Jump) -> Jump; 8] = [
+    |j| *j,
+    |j| Jump { x: -j.x, y: -j.y },
+    |j| Jump { x: -j.y, y: j.x },
+    |j| Jump { x: j.y, y: -j.x },
+    |j| Jump { x: j.x, y: -j.y },
+    |j| Jump { x: -j.x, y: j.y },
+    |j| Jump { x: j.y, y: j.x },
+    |j| Jump { x: -j.y, y: -j.x },
+];
+
+/// A choice of the distinct images of the move under the symmetries
+fn images(mov: &MoveCompact, symmetries: &[fn(&Jump) -> Jump]) -> MoveCompact {
+    let mut images: Vec<MoveCompact> = Vec::new();
+    for symmetry in symmetries {
+        let image = mov.map(symmetry);
+        if !images.contains(&image) {
+            images.push(image);
+        }
+    }
+    MoveCompact::Choice(images)
+}
+
+/// The choice a mirror or symmetry modifier makes of the move, or None if the modifier isn't one
+fn symmetric_choice(mov: &MoveCompact, modifier: &Mod) -> Option<MoveCompact> {
+    let mirrored = |mirror: fn(&Jump) -> Jump| {
+        Some(MoveCompact::Choice(vec![mov.map(mirror), (*mov).clone()]))
+    };
+    match modifier {
+        Mod::HorizontalMirror => mirrored(|j| Jump { x: j.x, y: -j.y }),
+        Mod::VerticalMirror => mirrored(|j| Jump { x: -j.x, y: j.y }),
+        Mod::DiagonalMirror => mirrored(|j| Jump { x: j.y, y: j.x }),
+        Mod::AntiDiagonalMirror => mirrored(|j| Jump { x: -j.y, y: -j.x }),
+        Mod::HalfTurn => Some(images(mov, &SYMMETRIES[..2])),
+        Mod::QuarterTurns => Some(images(mov, &SYMMETRIES[..4])),
+        Mod::FullSymmetry => Some(images(mov, &SYMMETRIES)),
+        _ => None,
+    }
+}
+
+/// The distinct moves that the move chooses between, looking through choices, mirrors and symmetries
+fn branches(mov: &MoveCompact) -> Vec<MoveCompact> {
+    let all = match mov {
+        MoveCompact::Choice(choices) => choices.iter().flat_map(branches).collect(),
+        MoveCompact::Named(_, mov) => branches(mov),
+        //each branch is still part of the labelled move
+        MoveCompact::Labelled(label, mov) => branches(mov)
+            .into_iter()
+            .map(|b| MoveCompact::Labelled(label.clone(), Box::new(b)))
+            .collect(),
+        MoveCompact::Modded(base, modifier) => match symmetric_choice(base, modifier) {
+            Some(choice) => branches(&choice),
+            None => vec![mov.clone()],
+        },
+        _ => vec![mov.clone()],
+    };
+    let mut distinct: Vec<MoveCompact> = Vec::new();
+    for branch in all {
+        if !distinct.contains(&branch) {
+            distinct.push(branch);
         }
     }
+    distinct
 }
 
 impl MoveGraph {
+    /// Builds the graph of the move, without deflating it
+    pub(crate) fn build(input: &MoveCompact) -> Self {
+        let mut r = MoveGraph {
+            graph: petgraph::stable_graph::StableDiGraph::<(), EdgeType, DefaultIx>::with_capacity(
+                0, 0,
+            ),
+            head: NodeIndex::<DefaultIx>::default(),
+            board_relative: match input.depends_on_dimensions() {
+                true => Some(BoardRelative {
+                    input: input.clone(),
+                    passes: Vec::new(),
+                    resolved: Resolved::default(),
+                }),
+                false => None,
+            },
+            labels: Vec::new(),
+        };
+        let (h, _) = r.build_from_node(input);
+        r.head = h;
+        r
+    }
+
     fn build_from_node(
         &mut self,
         node: &MoveCompact,
@@ -186,7 +1068,57 @@ impl MoveGraph {
 
                 (head_idx, tail_idx)
             }
+            MoveCompact::Unordered(moves) => {
+                //a node for each set of the moves that have been made so far, from which a copy of each other move is made
+                let subsets: Vec<NodeIndex<DefaultIx>> = (0..1usize << moves.len())
+                    .map(|_| self.graph.add_node(()))
+                    .collect();
+                for (made, node) in subsets.iter().enumerate() {
+                    for (i, m) in moves.iter().enumerate() {
+                        if made & (1 << i) == 0 {
+                            let (h, t) = self.build_from_node(m);
+                            self.graph.add_edge(*node, h, EdgeType::DummyRequired);
+                            self.graph.add_edge(
+                                t,
+                                subsets[made | (1 << i)],
+                                EdgeType::DummyRequired,
+                            );
+                        }
+                    }
+                }
+                (subsets[0], subsets[subsets.len() - 1])
+            }
             MoveCompact::Modded(mov, modifier) => self.build_from_mod(mov, modifier),
+            MoveCompact::Named(_, mov) => self.build_from_node(mov),
+            //the move hasn't been instantiated, so it doesn't know which jump to make
+            MoveCompact::JumpTemplate(_, _) => self.build_impossible(),
+            MoveCompact::Guard(j, c) => {
+                let h = self.graph.add_node(());
+                let t = self.graph.add_node(());
+                self.graph.add_edge(h, t, EdgeType::Guard(*j, *c));
+                (h, t)
+            }
+            MoveCompact::Turn(eighths) => {
+                let h = self.graph.add_node(());
+                let t = self.graph.add_node(());
+                self.graph.add_edge(h, t, EdgeType::Turn(*eighths));
+                (h, t)
+            }
+            MoveCompact::Intersection(lhs, rhs) => self.build_product(lhs, rhs, true),
+            MoveCompact::Difference(lhs, rhs) => self.build_product(lhs, rhs, false),
+            MoveCompact::Labelled(label, mov) => {
+                let index = match self.labels.iter().position(|l| l == label) {
+                    Some(index) => index,
+                    None => {
+                        self.labels.push(label.clone());
+                        self.labels.len() - 1
+                    }
+                };
+                let h = self.graph.add_node(());
+                let (h_mid, t) = self.build_from_node(mov);
+                self.graph.add_edge(h, h_mid, EdgeType::Label(index));
+                (h, t)
+            }
         }
     }
 
@@ -196,18 +1128,19 @@ impl MoveGraph {
         modifier: &Mod,
     ) -> (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>) {
         match modifier {
-            Mod::HorizontalMirror => self.build_from_node(&MoveCompact::Choice(vec![
-                mov.map(|j| Jump { x: j.x, y: -j.y }),
-                (*mov).clone(),
-            ])),
-            Mod::VerticalMirror => self.build_from_node(&MoveCompact::Choice(vec![
-                mov.map(|j| Jump { x: -j.x, y: j.y }),
-                (*mov).clone(),
-            ])),
-            Mod::DiagonalMirror => self.build_from_node(&MoveCompact::Choice(vec![
-                mov.map(|j| Jump { x: j.y, y: j.x }),
-                (*mov).clone(),
-            ])),
+            Mod::HorizontalMirror
+            | Mod::VerticalMirror
+            | Mod::DiagonalMirror
+            | Mod::AntiDiagonalMirror
+            | Mod::HalfTurn
+            | Mod::QuarterTurns
+            | Mod::FullSymmetry => self.build_from_node(&symmetric_choice(mov, modifier).unwrap()),
+            Mod::Locked(exponent) => self.build_from_node(&MoveCompact::Choice(
+                branches(mov)
+                    .into_iter()
+                    .map(|b| MoveCompact::Modded(Box::new(b), (**exponent).clone()))
+                    .collect(),
+            )),
             Mod::Exponentiate(exp) => {
                 if *exp == 0 {
                     let h = self.graph.add_node(());
@@ -223,17 +1156,52 @@ impl MoveGraph {
                     (h, t)
                 }
             }
-            Mod::ExponentiateRange(min, max) => {
-                let head = self.graph.add_node(());
-                let tail = self.graph.add_node(());
-                for exp in *min..=*max {
-                    let (h, t) = self.build_from_mod(mov, &Mod::Exponentiate(exp));
+            Mod::ExponentiateRange(min, max) => match (min, max) {
+                (Bound::Exactly(min), Bound::Exactly(max)) if min <= max => {
+                    let head = self.graph.add_node(());
+                    let tail = self.graph.add_node(());
+                    for exp in *min..=*max {
+                        let (h, t) = self.build_from_mod(mov, &Mod::Exponentiate(exp));
 
-                    self.graph.add_edge(head, h, EdgeType::DummyRequired);
-                    self.graph.add_edge(t, tail, EdgeType::DummyRequired);
+                        self.graph.add_edge(head, h, EdgeType::DummyRequired);
+                        self.graph.add_edge(t, tail, EdgeType::DummyRequired);
+                    }
+                    (head, tail)
                 }
-                (head, tail)
-            }
+                //the edge of the board stops the repetitions anyway
+                (Bound::Exactly(min), Bound::Edge) => {
+                    self.build_from_mod(mov, &Mod::ExponentiateInfinite(*min))
+                }
+                //the repetitions must reach the edge, so that one more would land off the board
+                (Bound::Edge, Bound::Exactly(_) | Bound::Edge) => match mov.displacement() {
+                    Some(d) if d != (Jump { x: 0, y: 0 }) => {
+                        //at least one repetition, so a piece already on the edge can't stand still
+                        let repetitions = match max {
+                            Bound::Exactly(max) => {
+                                Mod::ExponentiateRange(Bound::Exactly(1), Bound::Exactly(*max))
+                            }
+                            _ => Mod::ExponentiateInfinite(1),
+                        };
+                        //the piece must still be on the board, which an empty or occupied tile is
+                        let on_board = |c| MoveCompact::Guard(Jump { x: 0, y: 0 }, c);
+                        self.build_from_node(&MoveCompact::Sequence(vec![
+                            MoveCompact::Modded(Box::new(mov.clone()), repetitions),
+                            MoveCompact::Choice(vec![
+                                on_board(TileCondition::Empty),
+                                on_board(TileCondition::Occupied),
+                            ]),
+                            MoveCompact::Guard(d, TileCondition::Impassable),
+                        ]))
+                    }
+                    _ => self.build_impossible(),
+                },
+                //the range is empty, or depends on the size of a board that isn't known
+                _ => self.build_impossible(),
+            },
+            Mod::ExponentiateInfinite(0) => self.build_from_node(&MoveCompact::Choice(vec![
+                MoveCompact::Modded(Box::new(mov.clone()), Mod::Exponentiate(0)),
+                MoveCompact::Modded(Box::new(mov.clone()), Mod::ExponentiateInfinite(1)),
+            ])),
             Mod::ExponentiateInfinite(min) => {
                 let (h, t_mid) = self.build_from_mod(mov, &Mod::Exponentiate(*min - 1));
                 let (h_mid, t) = self.build_from_node(mov);
@@ -241,9 +1209,288 @@ impl MoveGraph {
                 self.merge(h_mid, t_mid);
                 (h, t)
             }
+            Mod::MoveOnly => self.build_with_test(mov, EdgeType::Condition(TileCondition::Empty)),
+            Mod::CaptureOnly => {
+                self.build_with_test(mov, EdgeType::Condition(TileCondition::Enemy))
+            }
+            Mod::Capture => self.build_with_test(mov, EdgeType::Capture),
+            Mod::Hurdle => self.build_with_test(mov, EdgeType::Hurdle),
+            Mod::Shoot => {
+                let h = self.graph.add_node(());
+                let (h_mid, t) = self.build_with_test(mov, EdgeType::ShotEnd);
+                self.graph.add_edge(h, h_mid, EdgeType::ShotStart);
+                (h, t)
+            }
+            Mod::Phasing => {
+                let (h, t) = self.build_from_node(mov);
+                //the move is not yet connected to the rest of the graph, so everything reachable from its head is part of it
+                let mut dfs = Dfs::new(&self.graph, h);
+                while let Some(n) = dfs.next(&self.graph) {
+                    let mut edges = self.graph.neighbors(n).detach();
+                    while let Some(e) = edges.next_edge(&self.graph) {
+                        if let EdgeType::Jump(j) = self.graph[e] {
+                            self.graph[e] = EdgeType::PhasingJump(j);
+                        }
+                    }
+                }
+                (h, t)
+            }
+            Mod::Bounce(limit) => self.build_bounce(mov, *limit),
+            Mod::ExponentiateParameter(_) => self.build_impossible(),
         }
     }
 
+    /// Builds the move once for each way it could have been reflected so far, and (if the bounces are limited) each number of bounces made.
+    /// Each jump of one copy can bounce to the jump, reflected, of the copy that is reflected in the axes it would leave the board across
+    fn build_bounce(
+        &mut self,
+        mov: &MoveCompact,
+        limit: Option<usize>,
+    ) -> (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>) {
+        let (h, t) = self.build_from_node(mov);
+        //the move is not yet connected to the rest of the graph, so everything reachable from its head is part of it
+        let mut nodes: Vec<NodeIndex<DefaultIx>> = Vec::new();
+        let mut dfs = Dfs::new(&self.graph, h);
+        while let Some(n) = dfs.next(&self.graph) {
+            nodes.push(n);
+        }
+        let edges: Vec<(NodeIndex<DefaultIx>, NodeIndex<DefaultIx>, EdgeType)> = nodes
+            .iter()
+            .flat_map(|n| self.graph.edges(*n))
+            .map(|e| (e.source(), e.target(), *e.weight()))
+            .collect();
+
+        const REFLECTIONS: [(bool, bool); 4] =
+            [(false, false), (true, false), (false, true), (true, true)];
+        let layers = limit.map_or(1, |limit| limit + 1);
+        let head = self.graph.add_node(());
+        let tail = self.graph.add_node(());
+        //the built move is the copy that hasn't bounced yet; the others map its nodes to theirs
+        type Nodes = HashMap<NodeIndex<DefaultIx>, NodeIndex<DefaultIx>>;
+        let mut copies: HashMap<((bool, bool), usize), Nodes> = HashMap::new();
+        for layer in 0..layers {
+            for reflection in REFLECTIONS {
+                //a counted copy can only be reflected once it has bounced
+                if limit.is_some() && layer == 0 && reflection != (false, false) {
+                    continue;
+                }
+                let copy: HashMap<_, _> = match (reflection, layer) {
+                    ((false, false), 0) => nodes.iter().map(|n| (*n, *n)).collect(),
+                    _ => nodes
+                        .iter()
+                        .map(|n| (*n, self.graph.add_node(())))
+                        .collect(),
+                };
+                if (reflection, layer) != ((false, false), 0) {
+                    for (source, target, e) in &edges {
+                        self.graph.add_edge(
+                            copy[source],
+                            copy[target],
+                            e.inverted(reflection.0, reflection.1),
+                        );
+                    }
+                }
+                self.graph.add_edge(copy[&t], tail, EdgeType::DummyRequired);
+                copies.insert((reflection, layer), copy);
+            }
+        }
+        self.graph.add_edge(head, h, EdgeType::DummyRequired);
+
+        for ((reflection, layer), copy) in &copies {
+            //unlimited bounces don't need counting
+            let next = match limit {
+                Some(_) => layer + 1,
+                None => *layer,
+            };
+            for (source, target, e) in &edges {
+                let step = match e.inverted(reflection.0, reflection.1) {
+                    EdgeType::Jump(j) | EdgeType::PhasingJump(j) => j,
+                    //what a relative step moves by is only known during the search, so there is no step to test for the edge;
+                    //the parser rejects them in bouncing moves
+                    EdgeType::Turn(_) => continue,
+                    //the other edges don't move the piece, so can't take it off the board
+                    EdgeType::DummyOptional
+                    | EdgeType::DummyRequired
+                    | EdgeType::Condition(_)
+                    | EdgeType::Hurdle
+                    | EdgeType::Guard(_, _)
+                    | EdgeType::Capture
+                    | EdgeType::ShotStart
+                    | EdgeType::ShotEnd
+                    | EdgeType::Bounce(_, _, _)
+                    | EdgeType::Label(_) => continue,
+                };
+                for flip in &REFLECTIONS[1..] {
+                    let reflected = (reflection.0 != flip.0, reflection.1 != flip.1);
+                    if let Some(bounced) = copies.get(&(reflected, next)) {
+                        let mid = self.graph.add_node(());
+                        self.graph.add_edge(
+                            copy[source],
+                            mid,
+                            EdgeType::Bounce(step, flip.0, flip.1),
+                        );
+                        self.graph.add_edge(
+                            mid,
+                            bounced[target],
+                            e.inverted(reflected.0, reflected.1),
+                        );
+                    }
+                }
+            }
+        }
+        (head, tail)
+    }
+
+    /// Builds the move, followed by an edge that tests the tile it lands on
+    fn build_with_test(
+        &mut self,
+        mov: &MoveCompact,
+        test: EdgeType,
+    ) -> (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>) {
+        let (h, t_mid) = self.build_from_node(mov);
+        let t = self.graph.add_node(());
+        self.graph.add_edge(t_mid, t, test);
+        (h, t)
+    }
+
+    /// Builds the sequences of edges that the left move can take, and that the right move can (if `shared`) or can't take.
+    /// This follows both graphs at once: the left one as it is, and the right one as the set of nodes it could have reached,
+    /// taking the same edges in both
+    fn build_product(
+        &mut self,
+        lhs: &MoveCompact,
+        rhs: &MoveCompact,
+        shared: bool,
+    ) -> (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>) {
+        let (lhs_head, lhs_tail) = self.build_from_node(lhs);
+        let (rhs_head, rhs_tail) = self.build_from_node(rhs);
+        //neither move is connected to the rest of the graph yet, so everything reachable from their heads is part of them
+        let mut operands: Vec<NodeIndex<DefaultIx>> = Vec::new();
+        for h in [lhs_head, rhs_head] {
+            let mut dfs = Dfs::new(&self.graph, h);
+            while let Some(n) = dfs.next(&self.graph) {
+                operands.push(n);
+            }
+        }
+
+        let is_dummy =
+            |e: &EdgeType| matches!(e, EdgeType::DummyOptional | EdgeType::DummyRequired);
+        //labels don't change the jumps a move makes, so they are kept from the left move, and looked through on the right
+        let is_silent = |e: &EdgeType| is_dummy(e) || matches!(e, EdgeType::Label(_));
+        //the nodes that can be reached from the given ones by following dummy edges and labels
+        let closure = |graph: &petgraph::stable_graph::StableDiGraph<(), EdgeType, DefaultIx>,
+                       mut nodes: Vec<NodeIndex<DefaultIx>>| {
+            let mut reached: BTreeSet<NodeIndex<DefaultIx>> = BTreeSet::new();
+            while let Some(n) = nodes.pop() {
+                if reached.insert(n) {
+                    nodes.extend(
+                        graph
+                            .edges(n)
+                            .filter(|e| is_silent(e.weight()))
+                            .map(|e| e.target()),
+                    );
+                }
+            }
+            reached
+        };
+
+        type ProductState = (NodeIndex<DefaultIx>, BTreeSet<NodeIndex<DefaultIx>>);
+        let start: ProductState = (lhs_head, closure(&self.graph, vec![rhs_head]));
+        let head = self.graph.add_node(());
+        let tail = self.graph.add_node(());
+        let mut nodes: HashMap<ProductState, NodeIndex<DefaultIx>> = HashMap::new();
+        nodes.insert(start.clone(), head);
+        let mut unexplored: Vec<ProductState> = vec![start];
+
+        while let Some(state) = unexplored.pop() {
+            let node = nodes[&state];
+            let (l, r) = &state;
+            if *l == lhs_tail && r.contains(&rhs_tail) == shared {
+                self.graph.add_edge(node, tail, EdgeType::DummyRequired);
+            }
+
+            let edges: Vec<(NodeIndex<DefaultIx>, EdgeType)> = self
+                .graph
+                .edges(*l)
+                .map(|e| (e.target(), *e.weight()))
+                .collect();
+            for (target, edge) in edges {
+                let next: ProductState = if is_silent(&edge) {
+                    (target, r.clone())
+                } else {
+                    let targets = r
+                        .iter()
+                        .flat_map(|n| self.graph.edges(*n))
+                        .filter(|e| *e.weight() == edge)
+                        .map(|e| e.target())
+                        .collect();
+                    (target, closure(&self.graph, targets))
+                };
+                if is_dummy(&edge) && next == state {
+                    //a dummy edge that doesn't go anywhere
+                    continue;
+                }
+                let next_node = match nodes.get(&next) {
+                    Some(n) => *n,
+                    None => {
+                        let n = self.graph.add_node(());
+                        nodes.insert(next.clone(), n);
+                        unexplored.push(next);
+                        n
+                    }
+                };
+                self.graph.add_edge(
+                    node,
+                    next_node,
+                    if is_dummy(&edge) {
+                        EdgeType::DummyRequired
+                    } else {
+                        edge
+                    },
+                );
+            }
+        }
+
+        for n in operands {
+            self.graph.remove_node(n);
+        }
+
+        //remove the nodes that can't lead to the end of the move; a node with no way out would otherwise let the move finish there
+        let mut finishing: HashSet<NodeIndex<DefaultIx>> = HashSet::new();
+        let mut unexplored: Vec<NodeIndex<DefaultIx>> = vec![tail];
+        while let Some(n) = unexplored.pop() {
+            if finishing.insert(n) {
+                unexplored.extend(self.graph.neighbors_directed(n, EdgeDirection::Incoming));
+            }
+        }
+        for n in nodes.into_values() {
+            if !finishing.contains(&n) {
+                self.graph.remove_node(n);
+            }
+        }
+
+        if finishing.contains(&head) {
+            (head, tail)
+        } else {
+            //there are no such sequences
+            self.graph.remove_node(tail);
+            self.build_impossible()
+        }
+    }
+
+    /// Builds a move that can never be made
+    fn build_impossible(&mut self) -> (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>) {
+        //a tile can't be both empty and occupied, so these tests never let the move through
+        let head = self.graph.add_node(());
+        let mid = self.graph.add_node(());
+        let tail = self.graph.add_node(());
+        self.graph
+            .add_edge(head, mid, EdgeType::Condition(TileCondition::Empty));
+        self.graph
+            .add_edge(mid, tail, EdgeType::Condition(TileCondition::Occupied));
+        (head, tail)
+    }
+
     fn merge(&mut self, to_keep: NodeIndex<DefaultIx>, to_drop: NodeIndex<DefaultIx>) {
         let drop_outgoing: Vec<(NodeIndex<DefaultIx>, EdgeType)> = self
             .graph
@@ -265,6 +1512,9 @@ impl MoveGraph {
             self.graph.add_edge(source, to_keep, weight);
         }
         self.graph.remove_node(to_drop);
+        if self.head == to_drop {
+            self.head = to_keep;
+        }
     }
 
     pub fn successors(
@@ -282,6 +1532,7 @@ impl MoveGraph {
         self.graph.edges(idx)
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn all_outgoing(
         &self,
         idx: NodeIndex<DefaultIx>,
@@ -296,106 +1547,163 @@ impl MoveGraph {
         self.head
     }
 
+    /// The labels of the move, in the order they were first built; `EdgeType::Label` indexes into these
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The graph to search on the board: if the move has exponents that depend on the size of the board,
+    /// it is built with them resolved, and compiled with the same passes as this graph.
+    /// This is done once for each size of board, unless the compiler has already done it.
+    pub(crate) fn on_board<B>(&self, board: &B) -> OnBoard<'_, MoveGraph>
+    where
+        B: Board,
+    {
+        match &self.board_relative {
+            Some(relative) => {
+                OnBoard::Resolved(resolve(&relative.resolved, board.dimensions(), || {
+                    compile_on_board(&relative.passes, &relative.input, board.dimensions())
+                }))
+            }
+            None => OnBoard::Borrowed(self),
+        }
+    }
+
+    pub(crate) fn is_board_relative(&self) -> bool {
+        self.board_relative.is_some()
+    }
+
+    /// Records the passes the graph was compiled with, so that the graphs built for each board are compiled the same way
+    pub(crate) fn set_passes(&mut self, passes: &[Pass]) {
+        if let Some(relative) = &mut self.board_relative {
+            relative.passes = passes.to_vec();
+            relative.resolved = Resolved::default();
+        }
+    }
+
+    /// Records the graph the compiler built for a board of these dimensions, which is then searched on boards of that size
+    pub(crate) fn set_on_board(&mut self, dimensions: Option<(i32, i32)>, graph: MoveGraph) {
+        if let Some(relative) = &self.board_relative {
+            resolve(&relative.resolved, dimensions, || graph);
+        }
+    }
+
     //TODO consider deflating by combining identical subgraphs
     // This can be done by seeing if two nodes have identival successor sets, and if so, merging them
 
     ///deflate the graph by removing superfluous nodes
-    fn deflate(&mut self) {
+    pub(crate) fn deflate(&mut self) {
         //TODO this loop is probably not the most efficient way to solve this problem...
-        loop {
-            //Find a reason to merge nodes
-
-            let mut edge = None;
-
-            //Reason 2: If there are outgoing required dummy edges, where  the nodes these lead to have no extra incoming edges (beyond the ones to the current node)
-            //then we can merge said children nodes the the current node
-            //i.e merge multi-layer choice nodes
-
-            edge = edge.or_else(|| {
-                self.graph.node_indices().find_map(|n| {
-                    let mergable = self
-                        .outgoing_edges(n)
-                        .filter(
-                            //filter out non-dummy edges
-                            |e| match e.weight() {
-                                EdgeType::Jump(_) => false,
-                                EdgeType::DummyOptional => false,
-                                EdgeType::DummyRequired => true,
-                            },
-                        )
-                        .filter(
-                            //filter out child nodes that have >1 incoming edge
-                            |e| {
-                                self.graph
-                                    .edges_directed(e.target(), EdgeDirection::Incoming)
-                                    .count()
-                                    == 1
-                            },
-                        )
-                        .filter(
-                            //filter out child nodes that have an edge to the parent
-                            |e| {
-                                self.graph
-                                    .edges_directed(e.target(), EdgeDirection::Outgoing)
-                                    .all(|ed| ed.target() != e.source())
-                            },
-                        );
+        //Find a reason to merge nodes, until there are none left
+        while self.merge_choice_layers() || self.collapse_dummy_edge() {}
 
-                    //return the edges to the mergable nodes
-                    let es = mergable
-                        .map(|e| (e.source(), e.target()))
-                        .collect::<Vec<(NodeIndex<DefaultIx>, NodeIndex<DefaultIx>)>>();
-                    match es.len() {
-                        0 => None,
-                        _ => Some(es),
-                    }
-                })
-                //can only merge into one node at a time, to prevent mergeing into a node that has been merged away
-            });
-            //the above could return several edges to remove at once; they should be mutually removable
-
-            //Reason 1: if there is only one outgoing edge, and it is a dummy type, we can merge the nodes
-            edge = edge.or_else(|| {
-                let a = self
-                    .graph
-                    .node_indices()
-                    .filter_map(|n| {
-                        let es: Vec<EdgeReference<EdgeType, DefaultIx>> =
-                            self.outgoing_edges(n).collect();
-                        if es.len() == 1 {
-                            let e = es[0];
-                            return match e.weight() {
-                                EdgeType::Jump(_) => None,
-                                EdgeType::DummyOptional => None,
-                                EdgeType::DummyRequired => Some((e.source(), e.target())),
-                            };
-                        }
-                        None
-                    })
-                    .take(1)
-                    //this sort of removal can only allow for one removal at a time, otherwise we may try to merge into a node that has already been deleted
-                    .collect::<Vec<(NodeIndex<DefaultIx>, NodeIndex<DefaultIx>)>>();
+        //println!("{:?}", petgraph::dot::Dot::with_config(&self.graph, &[]))
+    }
 
-                match a.len() {
-                    0 => None,
-                    _ => Some(a),
-                }
-            });
-
-            //remove an edge
-            match edge {
-                Some(vec) => {
-                    vec.into_iter().for_each(|(s, t)| {
-                        //there is an edge between the two nodes, this is kept during merging, so remove it first
-                        self.graph.remove_edge(self.graph.find_edge(s, t).unwrap());
-                        self.merge(s, t);
-                    });
-                    continue;
-                }
-                None => break,
+    /// Merges the nodes of one multi-layer choice, if there are any. Returns whether anything was merged.
+    pub(crate) fn merge_choice_layers(&mut self) -> bool {
+        //Reason 2: If there are outgoing required dummy edges, where  the nodes these lead to have no extra incoming edges (beyond the ones to the current node)
+        //then we can merge said children nodes the the current node
+        //i.e merge multi-layer choice nodes
+
+        let edges = self.graph.node_indices().find_map(|n| {
+            let mergable = self
+                .outgoing_edges(n)
+                .filter(
+                    //filter out non-dummy edges
+                    |e| match e.weight() {
+                        EdgeType::Jump(_) => false,
+                        EdgeType::DummyOptional => false,
+                        EdgeType::DummyRequired => true,
+                        EdgeType::Condition(_) => false,
+                        EdgeType::Hurdle => false,
+                        EdgeType::Guard(_, _) => false,
+                        EdgeType::PhasingJump(_) => false,
+                        EdgeType::Capture => false,
+                        EdgeType::ShotStart => false,
+                        EdgeType::ShotEnd => false,
+                        EdgeType::Turn(_) => false,
+                        EdgeType::Bounce(_, _, _) => false,
+                        EdgeType::Label(_) => false,
+                    },
+                )
+                .filter(
+                    //filter out child nodes that have >1 incoming edge
+                    |e| {
+                        self.graph
+                            .edges_directed(e.target(), EdgeDirection::Incoming)
+                            .count()
+                            == 1
+                    },
+                )
+                .filter(
+                    //filter out child nodes that have an edge to the parent
+                    |e| {
+                        self.graph
+                            .edges_directed(e.target(), EdgeDirection::Outgoing)
+                            .all(|ed| ed.target() != e.source())
+                    },
+                );
+
+            //return the edges to the mergable nodes
+            let es = mergable
+                .map(|e| (e.source(), e.target()))
+                .collect::<Vec<(NodeIndex<DefaultIx>, NodeIndex<DefaultIx>)>>();
+            match es.len() {
+                0 => None,
+                _ => Some(es),
             }
-        }
+            //can only merge into one node at a time, to prevent mergeing into a node that has been merged away
+        });
+        //the above could return several edges to remove at once; they should be mutually removable
 
-        //println!("{:?}", petgraph::dot::Dot::with_config(&self.graph, &[]))
+        self.merge_along(edges)
+    }
+
+    /// Merges the ends of one dummy edge that is the only way out of its source, if there are any. Returns whether anything was merged.
+    pub(crate) fn collapse_dummy_edge(&mut self) -> bool {
+        //Reason 1: if there is only one outgoing edge, and it is a dummy type, we can merge the nodes
+        let edge = self.graph.node_indices().find_map(|n| {
+            let es: Vec<EdgeReference<EdgeType, DefaultIx>> = self.outgoing_edges(n).collect();
+            if es.len() == 1 {
+                let e = es[0];
+                return match e.weight() {
+                    EdgeType::Jump(_) => None,
+                    EdgeType::DummyOptional => None,
+                    EdgeType::DummyRequired => Some((e.source(), e.target())),
+                    EdgeType::Condition(_) => None,
+                    EdgeType::Hurdle => None,
+                    EdgeType::Guard(_, _) => None,
+                    EdgeType::PhasingJump(_) => None,
+                    EdgeType::Capture => None,
+                    EdgeType::ShotStart => None,
+                    EdgeType::ShotEnd => None,
+                    EdgeType::Turn(_) => None,
+                    EdgeType::Bounce(_, _, _) => None,
+                    EdgeType::Label(_) => None,
+                };
+            }
+            None
+        });
+        //this sort of removal can only allow for one removal at a time, otherwise we may try to merge into a node that has already been deleted
+
+        self.merge_along(edge.map(|e| vec![e]))
+    }
+
+    fn merge_along(
+        &mut self,
+        edges: Option<Vec<(NodeIndex<DefaultIx>, NodeIndex<DefaultIx>)>>,
+    ) -> bool {
+        match edges {
+            Some(vec) => {
+                vec.into_iter().for_each(|(s, t)| {
+                    //there is an edge between the two nodes, this is kept during merging, so remove it first
+                    self.graph.remove_edge(self.graph.find_edge(s, t).unwrap());
+                    self.merge(s, t);
+                });
+                true
+            }
+            None => false,
+        }
     }
 }
@@ -18,6 +18,15 @@ impl From<parser::Seq> for MoveCompact {
                     t => MoveCompact::Sequence(vec![head, t]),
                 }
             }
+
+            parser::Seq::Intersection(lhs, rhs) => MoveCompact::Intersection(
+                Box::new(MoveCompact::from(*lhs)),
+                Box::new(MoveCompact::from(*rhs)),
+            ),
+            parser::Seq::Difference(lhs, rhs) => MoveCompact::Difference(
+                Box::new(MoveCompact::from(*lhs)),
+                Box::new(MoveCompact::from(*rhs)),
+            ),
         }
     }
 }
@@ -39,7 +48,23 @@ impl From<parser::PieceOption> for MoveCompact {
     fn from(this: parser::PieceOption) -> Self {
         match this {
             parser::PieceOption::Jump(j) => MoveCompact::Jump(j),
+            parser::PieceOption::Guard(j, c) => MoveCompact::Guard(j, c),
+            parser::PieceOption::Turn(eighths) => MoveCompact::Turn(eighths),
+            parser::PieceOption::JumpRange(x, y) => MoveCompact::Choice(
+                crate::movespec::jump_range(x, y)
+                    .into_iter()
+                    .map(MoveCompact::Jump)
+                    .collect(),
+            ),
             parser::PieceOption::Move(m) => MoveCompact::from(*m),
+            parser::PieceOption::Named(name, m) => MoveCompact::Named(name, m),
+            parser::PieceOption::JumpTemplate(x, y) => MoveCompact::JumpTemplate(x, y),
+            parser::PieceOption::Labelled(label, m) => {
+                MoveCompact::Labelled(label, Box::new(MoveCompact::from(*m)))
+            }
+            parser::PieceOption::Unordered(moves) => {
+                MoveCompact::Unordered(moves.into_iter().map(MoveCompact::from).collect())
+            }
             parser::PieceOption::Options(moves) => {
                 let choices = moves
                     .into_iter()
@@ -1,8 +1,10 @@
-use std::{iter::Peekable, num::TryFromIntError, vec};
+use std::{collections::HashMap, fmt, iter::Peekable, num::TryFromIntError, vec};
 
 use peeking_take_while::PeekableExt;
 use thiserror::Error;
 
+use crate::TileCondition;
+
 mod deflator;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -10,28 +12,98 @@ pub enum Mod {
     HorizontalMirror,
     VerticalMirror,
     DiagonalMirror,
+    AntiDiagonalMirror, //reflects in the line y=-x
+    HalfTurn,           //the move, and the move rotated by 180 degrees
+    QuarterTurns,       //the move rotated by each multiple of 90 degrees
+    FullSymmetry,       //every rotation and reflection of the move that maps the board onto itself
     Exponentiate(usize),
-    ExponentiateRange(usize, usize), //bounds of the range of exponents
+    ExponentiateParameter(String),   //an exponent given by a parameter
+    ExponentiateRange(Bound, Bound), //bounds of the range of exponents
     ExponentiateInfinite(usize),     //lower bound of exponent
+    MoveOnly,                        //the final landing must be on an empty tile
+    CaptureOnly,                     //the final landing must be on an enemy
+    Capture, //the final landing must be on an enemy, which is captured; the move may carry on from there
+    Hurdle,  //the final landing must be on a piece, which the move then carries on over
+    Phasing, //the landings may be on pieces, which the move passes through
+    Shoot, //the final landing must be on an enemy, which is captured without the piece moving there
+    Bounce(Option<usize>), //steps that would leave the board are reflected off its edge, at most this many times
+    Locked(Box<Mod>),      //the exponent, applied to each branch of the move on its own
+}
+
+/// A bound of a range of exponents, which may depend on the board the piece is on
+#[derive(Debug, PartialEq, Clone)]
+pub enum Bound {
+    Exactly(usize),
+    /// A parameter, given a value when the move is instantiated
+    Parameter(String),
+    /// The width of the board, divided by this (rounding down)
+    Width(usize),
+    /// The height of the board, divided by this (rounding down)
+    Height(usize),
+    /// As many repetitions as it takes for the move to reach the edge of the board
+    Edge,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Modded {
     Modded(PieceOption, Vec<Mod>),
     One(PieceOption),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Seq {
     Moves(Modded, Box<Seq>),
     Modded(Modded),
+    Intersection(Box<Seq>, Box<Seq>),
+    Difference(Box<Seq>, Box<Seq>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum PieceOption {
     Options(Vec<Seq>),
     Move(Box<Seq>),
     Jump(Jump),
+    Guard(Jump, TileCondition), //a test of the tile at an offset from the piece
+    Unordered(Vec<Modded>),     //a sequence of the moves, in any order
+    Turn(u8), //the previous leg, turned anticlockwise by this many eighths of a turn
+    JumpRange((i32, i32), (i32, i32)), //bounds of the x and y components; every jump between them, except [0,0]
+    Named(String, Box<crate::movespec::MoveCompact>), //a reference to a move bound to the name
+    JumpTemplate(Component, Component), //a jump with parameters for one or both of its components
+    Labelled(String, Box<Seq>),        //a move tagged with a label
+}
+
+/// A component of a jump that may be a parameter, given a value when the move is instantiated
+#[derive(Debug, PartialEq, Clone)]
+pub enum Component {
+    Value(i32),
+    Parameter(String),
+    /// The negation of the parameter's value
+    Negated(String),
+}
+
+/// Moves bound to names, which moves parsed with them can refer to as `$name`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bindings {
+    moves: HashMap<String, crate::movespec::MoveCompact>,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the move to the name, returning the move that was bound to it before, if there was one
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        mov: crate::movespec::MoveCompact,
+    ) -> Option<crate::movespec::MoveCompact> {
+        self.moves.insert(name.into(), mov)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&crate::movespec::MoveCompact> {
+        self.moves.get(name)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -40,35 +112,75 @@ pub struct Jump {
     pub y: i32,
 }
 
+/// Where a character is in a spec, with lines and columns counted from 1
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 // TODO add positions where the error occurred to all errors
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Error)]
 pub enum ParsingError {
-    #[error("Expected one of {0:?}, found {1}, at character position {2}")]
-    ExpectedCharacter(Vec<&'static str>, char, usize),
-    #[error("parsing ended at character {0}, but the string is not ended ")]
-    ExpectedEOF(usize),
+    #[error("Expected one of {0:?}, found {1}, at {2}")]
+    ExpectedCharacter(Vec<&'static str>, char, Position),
+    #[error("parsing ended at {0}, but the string is not ended")]
+    ExpectedEOF(Position),
     #[error("Unexpected EOF (unknown position)")]
     UnexpectedEOF,
-    #[error("The exponent ({1}) at character position {2} is invalid: {0}")]
-    NotAValidExponent(TryFromIntError, i32, usize),
-    #[error("Expected an integer at character position {1} : {0}")]
-    IntegerParsingError(<i32 as std::str::FromStr>::Err, usize),
+    #[error("The exponent ({1}) at {2} is invalid: {0}")]
+    NotAValidExponent(TryFromIntError, i32, Position),
+    #[error("Expected an integer at {1} : {0}")]
+    IntegerParsingError(<i32 as std::str::FromStr>::Err, Position),
     #[error("[0,0] is not a valid jump (Unknown position_")]
     NotAValidJump,
     #[error(
         "Upper bound ({1}) in exponent range is less than lower bound ({0}) (unkown position)"
     )]
     UpperExpLessThanLower(usize, usize),
+    #[error("Upper bound ({1}) in jump range is less than lower bound ({0}) (unkown position)")]
+    UpperJumpLessThanLower(i32, i32),
+    #[error("The board cannot be divided by zero, at {0}")]
+    DivisionByZero(Position),
+    #[error("The range of exponents ending at {0} must have a number as its lower bound, as it has no upper bound")]
+    UnboundedBoardRelative(Position),
+    #[error("No move is bound to the name {0}, at {1}")]
+    UndefinedName(String, Position),
+    #[error("A move is already bound to the name {0}, at {1}")]
+    AlreadyDefined(String, Position),
+    #[error("A range of jumps can't have a parameter as a bound (unknown position)")]
+    ParameterInJumpRange,
+    #[error("{0} is the size of the board, and can't be used as a parameter, at {1}")]
+    ReservedParameter(String, Position),
+    #[error("The comment starting at {0} is never closed")]
+    UnterminatedComment(Position),
+    #[error("The move on the right of the set operation at {0} has labels, which would never be reported")]
+    LabelInSetOperand(Position),
+    #[error("The move bounced at {0} has relative steps, which can't be reflected off the edge of the board")]
+    TurnInBounce(Position),
 }
 
 pub(crate) fn parse_string(input: &str) -> Result<crate::movespec::MoveCompact, ParsingError> {
-    //TODO also filter out tabs
-    let mut a = input
-        .chars()
-        .enumerate()
-        .filter(|(_, x): &(usize, char)| *x != ' ')
+    parse_with_bindings(input, &Bindings::default())
+}
+
+pub(crate) fn parse_with_bindings(
+    input: &str,
+    bindings: &Bindings,
+) -> Result<crate::movespec::MoveCompact, ParsingError> {
+    let mut a = characters(input)?
+        .into_iter()
+        .map(|(position, _, c)| (position, c))
         .peekable();
-    let r = parse_seq(&mut a);
+    let bindings = &parse_definitions(&mut a, bindings)?;
+    let r = parse_set_expression(&mut a, bindings);
     match r {
         Ok(ast) => {
             match a.next() {
@@ -80,18 +192,182 @@ pub(crate) fn parse_string(input: &str) -> Result<crate::movespec::MoveCompact,
     }
 }
 
-fn parse_seq<T>(input: &mut Peekable<T>) -> Result<Seq, ParsingError>
+//the characters of the spec with their positions and byte offsets, leaving out whitespace, // line comments and (* block comments *)
+fn characters(input: &str) -> Result<Vec<(Position, usize, char)>, ParsingError> {
+    let mut characters = Vec::new();
+    let mut position = Position { line: 1, column: 1 };
+    let mut input = input.char_indices().peekable();
+    let next = |input: &mut Peekable<std::str::CharIndices>, position: &mut Position| {
+        let (offset, c) = input.next()?;
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+        Some((offset, c))
+    };
+
+    //a line comment has to start its line, as // anywhere else is two mirrors
+    let mut line_start = true;
+    loop {
+        let start = position;
+        let current = next(&mut input, &mut position);
+        let comment_allowed = line_start;
+        line_start = match current {
+            Some((_, '\n')) => true,
+            Some((_, c)) => line_start && c.is_whitespace(),
+            None => false,
+        };
+        match (current, input.peek()) {
+            (None, _) => return Ok(characters),
+            (Some((_, '/')), Some((_, '/'))) if comment_allowed => {
+                while !matches!(next(&mut input, &mut position), Some((_, '\n')) | None) {}
+                line_start = true;
+            }
+            (Some((_, '(')), Some((_, '*'))) => {
+                next(&mut input, &mut position);
+                loop {
+                    match (next(&mut input, &mut position), input.peek()) {
+                        (None, _) => return Err(ParsingError::UnterminatedComment(start)),
+                        (Some((_, '*')), Some((_, ')'))) => {
+                            next(&mut input, &mut position);
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            (Some((_, c)), _) if c.is_whitespace() => (),
+            (Some((offset, c)), _) => characters.push((start, offset, c)),
+        }
+    }
+}
+
+/**
+Rewrites the spec to write its sequences with `.` rather than `*`, leaving everything else, including its layout and comments, as it was.
+The spec is not otherwise checked, so it may refer to moves that are only bound when it is parsed.
+*/
+pub fn migrate_sequences(spec: &str) -> Result<String, ParsingError> {
+    let characters = characters(spec)?;
+    let mut migrated = String::from(spec);
+    for (i, (_, offset, c)) in characters.iter().enumerate() {
+        let before = |n: usize| i.checked_sub(n).map(|j| characters[j].2);
+        //a * straight after ^, ^! or .. is an infinite exponent, and any other is a sequence
+        let exponent = match before(1) {
+            Some('^' | '.') => true,
+            Some('!') => before(2) == Some('^'),
+            _ => false,
+        };
+        if *c == '*' && !exponent {
+            migrated.replace_range(*offset..offset + 1, ".");
+        }
+    }
+    Ok(migrated)
+}
+
+//the lets at the start of the string, each binding a name to a move that the rest of the string can refer to
+fn parse_definitions<T>(
+    input: &mut Peekable<T>,
+    bindings: &Bindings,
+) -> Result<Bindings, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let mut bindings = bindings.clone();
+    while let Some((_, 'l')) = input.peek() {
+        for expected in ["l", "e", "t", "$"] {
+            match input.next() {
+                Some((_, c)) if expected.starts_with(c) => (),
+                Some((idx, c)) => {
+                    return Err(ParsingError::ExpectedCharacter(vec![expected], c, idx))
+                }
+                None => return Err(ParsingError::UnexpectedEOF),
+            }
+        }
+        let (idx, name) = parse_name(input)?;
+        if bindings.get(&name).is_some() {
+            return Err(ParsingError::AlreadyDefined(name, idx));
+        }
+        match input.next() {
+            Some((_, '=')) => (),
+            Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["="], c, idx)),
+            None => return Err(ParsingError::UnexpectedEOF),
+        }
+        let mov = crate::movespec::MoveCompact::from(parse_set_expression(input, &bindings)?);
+        match input.next() {
+            Some((_, ';')) => (),
+            Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec![";"], c, idx)),
+            None => return Err(ParsingError::UnexpectedEOF),
+        }
+        bindings.insert(name, mov);
+    }
+    Ok(bindings)
+}
+
+//the name after a $, along with where it starts
+fn parse_name<T>(input: &mut Peekable<T>) -> Result<(Position, String), ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let idx = match input.peek() {
+        Some((idx, c)) if c.is_ascii_alphanumeric() || *c == '_' => *idx,
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["name"], *c, *idx)),
+        None => return Err(ParsingError::UnexpectedEOF),
+    };
+    let name = input
+        .peeking_take_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+        .map(|(_, c)| c)
+        .collect();
+    Ok((idx, name))
+}
+
+//intersections and differences of sequences, which bind looser than the sequences, from left to right
+fn parse_set_expression<T>(
+    input: &mut Peekable<T>,
+    bindings: &Bindings,
+) -> Result<Seq, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let mut lhs = parse_seq(input, bindings)?;
+    while let Some((_, '&')) = input.peek() {
+        input.next();
+        let difference = matches!(input.peek(), Some((_, '!')));
+        if difference {
+            input.next();
+        }
+        let idx = match input.peek() {
+            Some((idx, _)) => *idx,
+            None => return Err(ParsingError::UnexpectedEOF),
+        };
+        let rhs = Box::new(parse_seq(input, bindings)?);
+        //only the jumps of the left move are made, so labels on the right could never be crossed
+        if crate::movespec::MoveCompact::from((*rhs).clone()).has_labels() {
+            return Err(ParsingError::LabelInSetOperand(idx));
+        }
+        lhs = if difference {
+            Seq::Difference(Box::new(lhs), rhs)
+        } else {
+            Seq::Intersection(Box::new(lhs), rhs)
+        };
+    }
+    Ok(lhs)
+}
+
+fn parse_seq<T>(input: &mut Peekable<T>, bindings: &Bindings) -> Result<Seq, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
-    let lhs: Result<Modded, ParsingError> = parse_modded(input);
+    let lhs: Result<Modded, ParsingError> = parse_unordered(input, bindings);
 
     match lhs {
         Err(e) => Err(e),
         Ok(ast) => match input.peek() {
-            Some((_, '*')) => {
+            //. is the same as *, and can't be confused with the infinite exponent
+            Some((_, '*' | '.')) => {
                 input.next();
-                let rhs = parse_seq(input);
+                let rhs = parse_seq(input, bindings);
                 rhs.map(|rast| Seq::Moves(ast, Box::new(rast)))
             }
             _ => Ok(Seq::Modded(ast)),
@@ -99,19 +375,61 @@ where
     }
 }
 
-fn parse_modded<T>(input: &mut Peekable<T>) -> Result<Modded, ParsingError>
+//moves joined by <>, which bind tighter than a sequence
+fn parse_unordered<T>(input: &mut Peekable<T>, bindings: &Bindings) -> Result<Modded, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
-    let lhs: Result<PieceOption, ParsingError> = parse_option(input);
+    let first = parse_modded(input, bindings)?;
+    if !matches!(input.peek(), Some((_, '<'))) {
+        return Ok(first);
+    }
+
+    let mut moves: Vec<Modded> = vec![first];
+    while let Some((_, '<')) = input.peek() {
+        input.next();
+        match input.next() {
+            Some((_, '>')) => (),
+            None => return Err(ParsingError::UnexpectedEOF),
+            Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec![">"], c, idx)),
+        }
+        moves.push(parse_modded(input, bindings)?);
+    }
+    Ok(Modded::One(PieceOption::Unordered(moves)))
+}
+
+fn parse_modded<T>(input: &mut Peekable<T>, bindings: &Bindings) -> Result<Modded, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let lhs: Result<PieceOption, ParsingError> = parse_option(input, bindings);
 
     match lhs {
         Ok(ast) => {
             let mut mods: Vec<Mod> = Vec::new();
             while matches!(
                 input.peek(),
-                Some((_, '|')) | Some((_, '/')) | Some((_, '-')) | Some((_, '^')) | Some((_, '?'))
+                Some((_, '|'))
+                    | Some((_, '/'))
+                    | Some((_, '-'))
+                    | Some((_, '^'))
+                    | Some((_, '?'))
+                    | Some((_, '~'))
+                    | Some((_, '!'))
+                    | Some((_, '+'))
+                    | Some((_, ':'))
+                    | Some((_, '='))
+                    | Some((_, '>'))
+                    | Some((_, '%'))
+                    | Some((_, '\\'))
+                    | Some((_, '@'))
             ) {
+                //a relative step only knows what it moves by during the search, so can't be reflected when it would leave the board
+                if let Some((idx, '%')) = input.peek() {
+                    if crate::movespec::MoveCompact::from(ast.clone()).has_turns() {
+                        return Err(ParsingError::TurnInBounce(*idx));
+                    }
+                }
                 let modifier = parse_mod(input)?;
                 mods.push(modifier);
             }
@@ -128,16 +446,36 @@ where
 
 fn parse_mod<T>(input: &mut Peekable<T>) -> Result<Mod, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
     match input.next() {
         Some((_, '|')) => Ok(Mod::VerticalMirror),
         Some((_, '/')) => Ok(Mod::DiagonalMirror),
         Some((_, '-')) => Ok(Mod::HorizontalMirror),
         Some((_, '^')) => parse_exponentiation_modifier(input),
-        Some((_, '?')) => Ok(Mod::ExponentiateRange(0, 1)), //? is syntactical sugar for ^[0..1]
+        Some((_, '?')) => Ok(Mod::ExponentiateRange(Bound::Exactly(0), Bound::Exactly(1))), //? is syntactical sugar for ^[0..1]
+        Some((_, '~')) => Ok(Mod::MoveOnly),
+        Some((_, '!')) => Ok(Mod::CaptureOnly),
+        Some((_, '+')) => Ok(Mod::Capture),
+        Some((_, ':')) => Ok(Mod::Hurdle),
+        Some((_, '=')) => Ok(Mod::Phasing),
+        Some((_, '>')) => Ok(Mod::Shoot),
+        Some((_, '%')) => match input.peek() {
+            Some((_, c)) if c.is_ascii_digit() => Ok(Mod::Bounce(Some(parse_usize(input)?))),
+            _ => Ok(Mod::Bounce(None)),
+        },
+        Some((_, '\\')) => Ok(Mod::AntiDiagonalMirror),
+        Some((_, '@')) => match input.next() {
+            Some((_, '2')) => Ok(Mod::HalfTurn),
+            Some((_, '4')) => Ok(Mod::QuarterTurns),
+            Some((_, '8')) => Ok(Mod::FullSymmetry),
+            Some((idx, c)) => Err(ParsingError::ExpectedCharacter(vec!["2", "4", "8"], c, idx)),
+            None => Err(ParsingError::UnexpectedEOF),
+        },
         Some((idx, c)) => Err(ParsingError::ExpectedCharacter(
-            vec!["|", "/", "-", "^"],
+            vec![
+                "|", "/", "-", "^", "?", "~", "!", "+", ":", "=", ">", "%", "\\", "@",
+            ],
             c,
             idx,
         )),
@@ -147,7 +485,21 @@ where
 
 fn parse_exponentiation_modifier<T>(input: &mut Peekable<T>) -> Result<Mod, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
+{
+    match input.peek() {
+        Some((_, '!')) => {
+            //the same branch is taken every time
+            input.next();
+            parse_exponent(input).map(|exponent| Mod::Locked(Box::new(exponent)))
+        }
+        _ => parse_exponent(input),
+    }
+}
+
+fn parse_exponent<T>(input: &mut Peekable<T>) -> Result<Mod, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
 {
     let (_, f) = match input.peek() {
         Some((i, f)) => (*i, *f),
@@ -156,7 +508,7 @@ where
     if f == '[' {
         //this is a range
         input.next();
-        let lower = parse_usize(input)?;
+        let lower = parse_bound(input)?;
         if !input.take(2).all(|(_, f)| f == '.') {
             let (i, c) = match input.peek() {
                 Some((i, f)) => (i, f),
@@ -168,9 +520,13 @@ where
             Some((i, c)) => (*i, *c),
             None => return Err(ParsingError::UnexpectedEOF),
         } {
-            (_, '*') => {
+            (i, '*') => {
                 //infinite range
                 input.next();
+                let lower = match lower {
+                    Bound::Exactly(lower) => lower,
+                    _ => return Err(ParsingError::UnboundedBoardRelative(i)),
+                };
 
                 match input.next() {
                     Some((_, ']')) => Ok(Mod::ExponentiateInfinite(lower)),
@@ -191,9 +547,12 @@ where
             | (_, '9') => {
                 //finite range
 
-                let upper = parse_usize(input)?;
-                if upper <= lower {
-                    return Err(ParsingError::UpperExpLessThanLower(lower, upper));
+                let upper = parse_bound(input)?;
+                //bounds that depend on the board can only be compared once the board is known
+                if let (Bound::Exactly(lower), Bound::Exactly(upper)) = (&lower, &upper) {
+                    if upper <= lower {
+                        return Err(ParsingError::UpperExpLessThanLower(*lower, *upper));
+                    }
                 }
                 match input.next() {
                     Some((_, ']')) => Ok(Mod::ExponentiateRange(lower, upper)),
@@ -201,8 +560,17 @@ where
                     Some((idx, c)) => Err(ParsingError::ExpectedCharacter(vec!["]"], c, idx)),
                 }
             }
+            (_, c) if c.is_ascii_lowercase() => {
+                //finite range, bounded by the board or a parameter
+                let upper = parse_bound(input)?;
+                match input.next() {
+                    Some((_, ']')) => Ok(Mod::ExponentiateRange(lower, upper)),
+                    None => Err(ParsingError::UnexpectedEOF),
+                    Some((idx, c)) => Err(ParsingError::ExpectedCharacter(vec!["]"], c, idx)),
+                }
+            }
             (i, c) => Err(ParsingError::ExpectedCharacter(
-                vec!["*", "non-negative integer"],
+                vec!["*", "non-negative integer", "w", "h", "e", "parameter"],
                 c,
                 i,
             )),
@@ -211,6 +579,13 @@ where
         input.next();
         //this is a single asterisk, signifying [1..*]
         Ok(Mod::ExponentiateInfinite(1))
+    } else if f.is_ascii_lowercase() {
+        //an exponent given by a parameter
+        let (idx, name) = parse_parameter(input)?;
+        match name.as_str() {
+            "w" | "h" | "e" => Err(ParsingError::ReservedParameter(name, idx)),
+            _ => Ok(Mod::ExponentiateParameter(name)),
+        }
     } else {
         //this is a single exponent
         let exp = parse_usize(input)?;
@@ -219,9 +594,58 @@ where
     }
 }
 
+//a bound of a range of exponents: a number, w or h (optionally divided by a number), e, or a parameter
+fn parse_bound<T>(input: &mut Peekable<T>) -> Result<Bound, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let name = match input.peek() {
+        Some((_, c)) if c.is_ascii_lowercase() => parse_parameter(input)?.1,
+        _ => return parse_usize(input).map(Bound::Exactly),
+    };
+    let dimension = match name.as_str() {
+        "e" => return Ok(Bound::Edge),
+        "w" => Bound::Width,
+        "h" => Bound::Height,
+        _ => return Ok(Bound::Parameter(name)),
+    };
+
+    match input.peek() {
+        Some((_, '/')) => {
+            input.next();
+            let idx = match input.peek() {
+                Some((idx, _)) => *idx,
+                None => return Err(ParsingError::UnexpectedEOF),
+            };
+            match parse_usize(input)? {
+                0 => Err(ParsingError::DivisionByZero(idx)),
+                divisor => Ok(dimension(divisor)),
+            }
+        }
+        _ => Ok(dimension(1)),
+    }
+}
+
+//the name of a parameter, which starts with a lowercase letter, along with where it starts
+fn parse_parameter<T>(input: &mut Peekable<T>) -> Result<(Position, String), ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let idx = match input.peek() {
+        Some((idx, c)) if c.is_ascii_lowercase() => *idx,
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["parameter"], *c, *idx)),
+        None => return Err(ParsingError::UnexpectedEOF),
+    };
+    let name = input
+        .peeking_take_while(|(_, c)| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_')
+        .map(|(_, c)| c)
+        .collect();
+    Ok((idx, name))
+}
+
 fn parse_usize<T>(input: &mut Peekable<T>) -> Result<usize, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
     let idx = match input.peek() {
         Some((idx, _)) => *idx,
@@ -236,9 +660,12 @@ where
     }
 }
 
-fn parse_option<T>(input: &mut Peekable<T>) -> Result<PieceOption, ParsingError>
+fn parse_option<T>(
+    input: &mut Peekable<T>,
+    bindings: &Bindings,
+) -> Result<PieceOption, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
     match input.peek() {
         Some((_, '{')) => {
@@ -249,10 +676,10 @@ where
                 Some((_, '}')) => Ok(PieceOption::Options(moves)), //empty option
                 Some(_) => {
                     //at least 1 option
-                    moves.push(parse_seq(input)?);
+                    moves.push(parse_set_expression(input, bindings)?);
                     loop {
                         match input.next() {
-                            Some((_, ',')) => moves.push(parse_seq(input)?),
+                            Some((_, ',')) => moves.push(parse_set_expression(input, bindings)?),
                             Some((_, '}')) => return Ok(PieceOption::Options(moves)),
                             None => return Err(ParsingError::UnexpectedEOF),
                             Some((i, c)) => {
@@ -266,7 +693,7 @@ where
         }
         Some((_, '(')) => {
             input.next();
-            let m = parse_seq(input);
+            let m = parse_set_expression(input, bindings);
 
             match input.next() {
                 Some((_, ')')) => m.map(|x| PieceOption::Move(Box::new(x))),
@@ -274,16 +701,179 @@ where
                 Some((idx, c)) => Err(ParsingError::ExpectedCharacter(vec![")"], c, idx)),
             }
         }
+        Some((_, '$')) => {
+            //a reference to a named move
+            input.next();
+            let (idx, name) = parse_name(input)?;
+            match bindings.get(&name) {
+                Some(mov) => Ok(PieceOption::Named(name, Box::new(mov.clone()))),
+                None => Err(ParsingError::UndefinedName(name, idx)),
+            }
+        }
+        Some((_, '#')) => {
+            //a labelled move, which must be bracketed
+            input.next();
+            let (_, label) = parse_name(input)?;
+            match input.next() {
+                Some((_, '(')) => (),
+                None => return Err(ParsingError::UnexpectedEOF),
+                Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["("], c, idx)),
+            }
+            let m = parse_set_expression(input, bindings)?;
+            match input.next() {
+                Some((_, ')')) => Ok(PieceOption::Labelled(label, Box::new(m))),
+                None => Err(ParsingError::UnexpectedEOF),
+                Some((idx, c)) => Err(ParsingError::ExpectedCharacter(vec![")"], c, idx)),
+            }
+        }
+        Some((_, '?')) => {
+            //a guard, which doesn't move the piece
+            input.next();
+            let condition = match input.peek() {
+                Some((_, ':')) => Some(TileCondition::Occupied),
+                Some((_, '!')) => Some(TileCondition::Enemy),
+                Some((_, '#')) => Some(TileCondition::Impassable),
+                _ => None,
+            };
+            if condition.is_some() {
+                input.next();
+            }
+            let offset = parse_jump(input)?;
+            Ok(PieceOption::Guard(
+                offset,
+                condition.unwrap_or(TileCondition::Empty),
+            ))
+        }
+        _ => parse_jump_range(input),
+    }
+}
+
+fn parse_jump_range<T>(input: &mut Peekable<T>) -> Result<PieceOption, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    match input.next() {
+        Some((_, '[')) => (),
+        None => return Err(ParsingError::UnexpectedEOF),
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["["], c, idx)),
+    };
+    if let Some((_, 'F' | 'L' | 'R')) = input.peek() {
+        return parse_turn(input);
+    }
+
+    let x = parse_jump_component(input)?;
+    match input.next() {
+        Some((_, ',')) => (),
+        None => return Err(ParsingError::UnexpectedEOF),
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec![","], c, idx)),
+    };
+
+    let y = parse_jump_component(input)?;
+    match input.next() {
+        Some((_, ']')) => (),
+        None => return Err(ParsingError::UnexpectedEOF),
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["]"], c, idx)),
+    };
+
+    if x.0 == x.1 && y.0 == y.1 {
+        //a single jump
+        return match (x.0, y.0) {
+            (Component::Value(0), Component::Value(0)) => Err(ParsingError::NotAValidJump),
+            (Component::Value(x), Component::Value(y)) => Ok(PieceOption::Jump(Jump { x, y })),
+            (x, y) => Ok(PieceOption::JumpTemplate(x, y)),
+        };
+    }
+
+    match (x, y) {
+        (
+            (Component::Value(x0), Component::Value(x1)),
+            (Component::Value(y0), Component::Value(y1)),
+        ) => Ok(PieceOption::JumpRange((x0, x1), (y0, y1))),
+        //a parameter can't be part of a range
+        _ => Err(ParsingError::ParameterInJumpRange),
+    }
+}
+
+//the inside of a relative step, [F], [L], [R], [LL], [RR] and so on, after the opening bracket
+fn parse_turn<T>(input: &mut Peekable<T>) -> Result<PieceOption, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let direction = match input.next() {
+        Some((_, d)) => d,
+        None => return Err(ParsingError::UnexpectedEOF),
+    };
+    let mut eighths: usize = 1;
+    if direction != 'F' {
+        eighths += input.peeking_take_while(|(_, c)| *c == direction).count();
+    }
+    match input.next() {
+        Some((_, ']')) => (),
+        None => return Err(ParsingError::UnexpectedEOF),
+        Some((idx, c)) => return Err(ParsingError::ExpectedCharacter(vec!["]"], c, idx)),
+    };
+
+    Ok(PieceOption::Turn(match direction {
+        'L' => (eighths % 8) as u8,
+        'R' => ((8 - eighths % 8) % 8) as u8,
+        _ => 0,
+    }))
+}
+
+//an integer, or a range of integers `a..b`, as the bounds of the range
+fn parse_jump_component<T>(input: &mut Peekable<T>) -> Result<(Component, Component), ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let lower = parse_component(input)?;
+    let dot = match input.peek() {
+        Some((i, '.')) => *i,
+        _ => return Ok((lower.clone(), lower)),
+    };
+    input.next();
+    match input.next() {
+        Some((_, '.')) => (),
+        None => return Err(ParsingError::UnexpectedEOF),
+        Some(_) => return Err(ParsingError::ExpectedCharacter(vec![".."], '.', dot)),
+    }
+    let upper = parse_component(input)?;
+    match (&lower, &upper) {
+        (Component::Value(l), Component::Value(u)) if u <= l => {
+            Err(ParsingError::UpperJumpLessThanLower(*l, *u))
+        }
+        (Component::Value(_), Component::Value(_)) => Ok((lower, upper)),
+        _ => Err(ParsingError::ParameterInJumpRange),
+    }
+}
+
+//a number, or a parameter that may be negated
+fn parse_component<T>(input: &mut Peekable<T>) -> Result<Component, ParsingError>
+where
+    T: Iterator<Item = (Position, char)>,
+{
+    let negated = matches!(input.peek(), Some((_, '-')));
+    if negated {
+        input.next();
+    }
+    match input.peek() {
+        Some((_, c)) if c.is_ascii_lowercase() => {
+            let (idx, name) = parse_parameter(input)?;
+            match (name.as_str(), negated) {
+                ("w" | "h" | "e", _) => Err(ParsingError::ReservedParameter(name, idx)),
+                (_, true) => Ok(Component::Negated(name)),
+                (_, false) => Ok(Component::Parameter(name)),
+            }
+        }
         _ => {
-            let jump: Result<Jump, ParsingError> = parse_jump(input);
-            jump.map(PieceOption::Jump)
+            let value = parse_integer(input)?;
+            Ok(Component::Value(if negated { -value } else { value }))
         }
     }
 }
 
 fn parse_jump<T>(input: &mut Peekable<T>) -> Result<Jump, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
     match input.next() {
         Some((_, '[')) => (),
@@ -317,9 +907,9 @@ where
 
 fn parse_integer<T>(input: &mut Peekable<T>) -> Result<i32, ParsingError>
 where
-    T: Iterator<Item = (usize, char)>,
+    T: Iterator<Item = (Position, char)>,
 {
-    let i: usize = match input.peek() {
+    let i: Position = match input.peek() {
         Some((i, _)) => *i,
         _ => return Err(ParsingError::UnexpectedEOF),
     };
@@ -366,7 +956,15 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::parse_string;
+    use crate::parser::{migrate_sequences, parse_string, ParsingError, Position};
+    use crate::{
+        Bindings, Bound, Component, Jump, Mod, MoveCompact, NotationOptions, TileCondition,
+    };
+
+    //a position on the first line
+    fn at(column: usize) -> Position {
+        Position { line: 1, column }
+    }
 
     #[test]
     fn jumps() {
@@ -394,6 +992,75 @@ mod tests {
         )
     }
 
+    #[test]
+    fn dot_sequences() {
+        for (dotted, starred) in [
+            ("[1,2]|-/.[0,1]?", "[1,2]|-/*[0,1]?"),
+            ("[1,0]^*.[0,1]^*", "[1,0]^**[0,1]^*"),
+            ("([1,0]^[2..*].[0,1])^!*", "([1,0]^[2..*]*[0,1])^!*"),
+            ("[1,0].[0,1]*[1,1]", "[1,0]*[0,1]*[1,1]"),
+            ("[1,1]!.[1,1]~", "[1,1]!*[1,1]~"),
+        ] {
+            assert_eq!(parse_string(dotted), parse_string(starred));
+            assert!(parse_string(dotted).is_ok(), "{}", dotted);
+        }
+
+        let dots = NotationOptions {
+            dot_sequences: true,
+            ..Default::default()
+        };
+        let rider = parse_string("[1,0]^**[0,1]^*").unwrap();
+        assert_eq!(rider.notation(), "[1,0]^**[0,1]^*");
+        assert_eq!(rider.notation_with(dots), "[1,0]^*.[0,1]^*");
+        for spec in [
+            "([1,1]^[2..*]:.[1,1])|-",
+            "{[1,0]^*|-/~,([1,0]^*:.[1,0]^*!)|-/}",
+            "#a([1,0].[0,1])^[0..*]",
+            "[1,0]<>[0,1].[1,1]^!*",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation_with(dots), spec);
+        }
+        let keep_dots = NotationOptions {
+            keep_references: true,
+            dot_sequences: true,
+        };
+        let spec = "let $rook = [1,0]^*|-/; $rook.[1,1]";
+        assert_eq!(parse_string(spec).unwrap().notation_with(keep_dots), spec);
+    }
+
+    #[test]
+    fn sequence_migration() {
+        for (spec, migrated) in [
+            ("[1,2]|-/*[0,1]?", "[1,2]|-/.[0,1]?"),
+            ("[1,0]^**[0,1]^*", "[1,0]^*.[0,1]^*"),
+            ("([1,0]^[2..*]*[0,1])^!*", "([1,0]^[2..*].[0,1])^!*"),
+            ("(([1,1]!*[1,1]~)|-)^*", "(([1,1]!.[1,1]~)|-)^*"),
+            ("[1,1]^2!*[0,1]", "[1,1]^2!.[0,1]"),
+            (
+                "let $a = [1,0]^!*; $a * #b([0,1]*?#[1,1])",
+                "let $a = [1,0]^!*; $a . #b([0,1].?#[1,1])",
+            ),
+            (
+                "[1,0]^ * (* rides, * then *) * [0,1]\n  // steps * once\n",
+                "[1,0]^ * (* rides, * then *) . [0,1]\n  // steps * once\n",
+            ),
+            ("[1,2]//*[0,1]\n//*", "[1,2]//.[0,1]\n//*"),
+            ("[1,2] // * [0,1]", "[1,2] // . [0,1]"),
+            //moves bound by the caller don't need to be known
+            ("$rook*[0,1]", "$rook.[0,1]"),
+            ("[1,0].[0,1]", "[1,0].[0,1]"),
+        ] {
+            assert_eq!(migrate_sequences(spec).unwrap(), migrated);
+            if !spec.contains("$rook") {
+                assert_eq!(parse_string(migrated), parse_string(spec));
+            }
+        }
+        assert_eq!(
+            migrate_sequences("[1,0]*(*[0,1]"),
+            Err(ParsingError::UnterminatedComment(at(7)))
+        );
+    }
+
     #[test]
     fn exponentiation() {
         let r2 = parse_string("{[1,1]^4,[-1,1]^4,[1,-1]^4,[1,-1]^4}");
@@ -411,6 +1078,9 @@ mod tests {
         assert!(r6.is_ok());
         assert_eq!(r6, r1);
 
+        let kleene = parse_string("[1,1]*[1,0]^[0..*]");
+        assert_eq!(kleene.unwrap().notation(), "[1,1]*[1,0]^[0..*]");
+
         //TODO still need to verify that, for example, r4 and r5 are equal. This cannot be done in the deflator, however, as it requires unrolling.
     }
 
@@ -423,20 +1093,43 @@ mod tests {
         assert!(rook.is_ok());
     }
 
+    #[test]
+    fn symmetries() {
+        for spec in [
+            "[1,2]\\",
+            "[1,2]@2",
+            "[1,0]^*@4",
+            "([1,1]*[1,0])@8",
+            "[1,0]@4|",
+        ] {
+            let parsed = parse_string(spec);
+            assert!(parsed.is_ok(), "{}", spec);
+            assert_eq!(parsed.unwrap().notation(), spec);
+        }
+        assert_eq!(
+            parse_string("[1,0]@3"),
+            Err(crate::parser::ParsingError::ExpectedCharacter(
+                vec!["2", "4", "8"],
+                '3',
+                at(7)
+            ))
+        );
+    }
+
     #[test]
     fn integer_parsing() {
         let knight = parse_string("[2,1]/|-");
         assert!(knight.is_ok());
         let knight = parse_string("[e,1]/|-");
         match knight.err().unwrap() {
-            crate::parser::ParsingError::ExpectedCharacter(_, _, i) => assert_eq!(i, 1),
+            crate::parser::ParsingError::ReservedParameter(_, i) => assert_eq!(i, at(2)),
             _ => panic!(),
         }
         let knight = parse_string("[2.2,1]/|-");
         assert!(knight.is_err());
         //println!("{:?}",knight);
         match knight.unwrap_err() {
-            crate::parser::ParsingError::ExpectedCharacter(_, _, i) => assert_eq!(i, 2), //the decimal point should cause an error
+            crate::parser::ParsingError::ExpectedCharacter(_, _, i) => assert_eq!(i, at(3)), //the decimal point should cause an error
             _ => panic!(),
         }
     }
@@ -460,8 +1153,573 @@ mod tests {
 
     #[test]
     fn allow_tabs() {
-        //TODO this isn't using proper tabs, it's spaces...
         let knight = parse_string("[2,  1]/     |-   ");
         assert!(knight.is_ok());
+        assert_eq!(parse_string("[2,\t1]\t/|-\r\n"), knight);
+        assert_eq!(parse_string("[2,1]\n/\n|\n-"), knight);
+    }
+
+    #[test]
+    fn comments() {
+        let pawn = parse_string("{[0,1]~,[1,1]|!}");
+        assert!(pawn.is_ok());
+        for spec in [
+            "{[0,1]~,[1,1]|!}\n// a pawn",
+            "{\n  // moves forward\n  [0,1]~,\n  // captures diagonally\n  [1,1]|!\n}",
+            "//a pawn\n{[0,1]~,[1,1]|!}",
+            "{[0,1]~, (* moves forward *)\n[1,1]|! (* captures diagonally *)\n}",
+            "{[0,1]~,(* moves forward *)[1,1]|!}",
+            "(* a pawn:\n moves forward, and\n captures diagonally *) {[0,1]~,[1,1]|!}",
+            "{[0,1]~,[1,1]|(**)!}",
+        ] {
+            assert_eq!(parse_string(spec), pawn, "{}", spec);
+        }
+        //comment markers inside a comment are just part of it
+        assert!(parse_string("{[0,1]~,[1,1]|!} (* // *)").is_ok());
+        assert!(parse_string("{[0,1]~,[1,1]|!}\n// (*").is_ok());
+
+        assert_eq!(
+            parse_string("{[0,1]~,\n  (* captures\n[1,1]|!}"),
+            Err(ParsingError::UnterminatedComment(Position {
+                line: 2,
+                column: 3
+            }))
+        );
+        //a sequence after a diagonal mirror is not a comment
+        assert!(parse_string("[1,2]/*[0,1]").is_ok());
+
+        //nor are two mirrors, unless they start a line
+        let knight = parse_string("[1,2]|-/");
+        for spec in [
+            "[1,2]|-/\n//a knight",
+            "[1,2]|-/\n\t // a knight",
+            "// a knight\n[1,2]|-/",
+            "[1,2]|-/ (* a knight *)",
+        ] {
+            assert_eq!(parse_string(spec), knight, "{}", spec);
+        }
+        assert!(parse_string("[1,2]|-//knight").is_err());
+        assert!(parse_string("[1,2]|-/ // a knight").is_err());
+        assert_eq!(parse_string("[1,2]//"), parse_string("([1,2]/)/"));
+        assert_ne!(parse_string("[1,2]//"), parse_string("[1,2]"));
+        //as they were before there were comments, even after whitespace
+        assert_eq!(parse_string("[1,2] //"), parse_string("([1,2]/)/"));
+        assert_eq!(parse_string("[1,2]|-\t//"), parse_string("(([1,2]|-)/)/"));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            parse_string("let $rook = [1,0]^*|-/;\n{\n\t$rook,\n\t$queen\n}"),
+            Err(ParsingError::UndefinedName(
+                String::from("queen"),
+                Position { line: 4, column: 3 }
+            ))
+        );
+        assert_eq!(
+            parse_string("(* a rook *)\n[1,0]^*|-/\n(* with a bad mirror *) |-@3"),
+            Err(ParsingError::ExpectedCharacter(
+                vec!["2", "4", "8"],
+                '3',
+                Position {
+                    line: 3,
+                    column: 28
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn move_and_capture_modifiers() {
+        let pawn = parse_string("{[0,1]~, [1,1]|!}");
+        assert!(pawn.is_ok());
+        assert_eq!(pawn.unwrap().notation(), "{[0,1]~,[1,1]|!}");
+
+        let cannon_ish = parse_string("([1,0]^*|-/)~");
+        assert!(cannon_ish.is_ok());
+        assert_eq!(cannon_ish.unwrap().notation(), "[1,0]^*|-/~");
+
+        //capturing and carrying on is a modifier of its own, not capture-only
+        let man = parse_string("(([1,1]+*[1,1]~)|)^*").unwrap();
+        assert_eq!(man.notation(), "([1,1]+*[1,1]~)|^*");
+        assert_ne!(Ok(man), parse_string("(([1,1]!*[1,1]~)|)^*"));
+    }
+
+    #[test]
+    fn hurdle_modifier() {
+        let grasshopper = parse_string("([1,0]^*:*[1,0])|-/");
+        assert!(grasshopper.is_ok());
+        assert_eq!(grasshopper.unwrap().notation(), "([1,0]^*:*[1,0])|-/");
+
+        assert!(parse_string("[1,0]:").is_ok());
+        assert!(parse_string(":[1,0]").is_err());
+    }
+
+    #[test]
+    fn phasing_modifier() {
+        let ghost = parse_string("([1,0]^*=)|-/");
+        assert!(ghost.is_ok());
+        assert_eq!(ghost.unwrap().notation(), "[1,0]^*=|-/");
+        assert_eq!(
+            parse_string("[1,1]=*[1,0]").unwrap().notation(),
+            "[1,1]=*[1,0]"
+        );
+    }
+
+    #[test]
+    fn shoot_modifier() {
+        let archer = parse_string("{[1,0]|-/~, ([1,0]^[1..3]>)|-/}");
+        assert!(archer.is_ok());
+        assert_eq!(archer.unwrap().notation(), "{[1,0]|-/~,[1,0]^[1..3]>|-/}");
+    }
+
+    #[test]
+    fn guards() {
+        let horse = parse_string("(?[0,1]*[1,2])|-/");
+        assert!(horse.is_ok());
+        assert_eq!(horse.unwrap().notation(), "(?[0,1]*[1,2])|-/");
+
+        for spec in ["?:[1,0]*[2,0]", "?![0,1]*[1,1]^2", "{?[1,1],[1,0]}"] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert_eq!(
+            parse_string("[1,0]?*?[0,1]").unwrap(),
+            MoveCompact::Sequence(vec![
+                MoveCompact::Modded(
+                    Box::new(MoveCompact::Jump(Jump { x: 1, y: 0 })),
+                    Mod::ExponentiateRange(Bound::Exactly(0), Bound::Exactly(1))
+                ),
+                MoveCompact::Guard(Jump { x: 0, y: 1 }, TileCondition::Empty),
+            ])
+        );
+        assert!(parse_string("?~[1,0]").is_err());
+        assert!(parse_string("?[0,0]").is_err());
+    }
+
+    #[test]
+    fn jump_ranges() {
+        assert_eq!(
+            parse_string("[-1..1,0..1]").unwrap(),
+            MoveCompact::Choice(vec![
+                MoveCompact::Jump(Jump { x: -1, y: 0 }),
+                MoveCompact::Jump(Jump { x: -1, y: 1 }),
+                MoveCompact::Jump(Jump { x: 0, y: 1 }),
+                MoveCompact::Jump(Jump { x: 1, y: 0 }),
+                MoveCompact::Jump(Jump { x: 1, y: 1 }),
+            ])
+        );
+        assert_eq!(
+            parse_string("[1..3,0]").unwrap(),
+            parse_string("{[1,0],[2,0],[3,0]}").unwrap()
+        );
+
+        for spec in ["[1..3,0]", "[-2..2,1]", "[0,-3..3]^*", "([1..2,1]*[0,1])|-"] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        //a choice of jumps that fills a range is printed as the range
+        assert_eq!(
+            parse_string("{[0,1],[1,0],[1,1]}").unwrap().notation(),
+            "[0..1,0..1]"
+        );
+        assert_eq!(
+            parse_string("{[1,0],[3,0]}").unwrap().notation(),
+            "{[1,0],[3,0]}"
+        );
+
+        assert_eq!(
+            parse_string("[3..1,0]"),
+            Err(ParsingError::UpperJumpLessThanLower(3, 1))
+        );
+        assert!(parse_string("[1..,0]").is_err());
+        assert!(parse_string("[1.2,0]").is_err());
+        assert!(parse_string("?[1..2,0]").is_err());
+    }
+
+    #[test]
+    fn set_operations() {
+        let pawn = MoveCompact::Modded(
+            Box::new(MoveCompact::Jump(Jump { x: 0, y: 1 })),
+            Mod::ExponentiateRange(Bound::Exactly(1), Bound::Exactly(2)),
+        );
+        let one = MoveCompact::Jump(Jump { x: 0, y: 1 });
+        assert_eq!(
+            parse_string("[0,1]^[1..2] &! [0,1]").unwrap(),
+            MoveCompact::Difference(Box::new(pawn.clone()), Box::new(one.clone()))
+        );
+        //sequences bind tighter than set operations
+        assert_eq!(
+            parse_string("[0,1]*[0,1]&[0,1]^[1..2]").unwrap(),
+            MoveCompact::Intersection(
+                Box::new(MoveCompact::Sequence(vec![one.clone(), one.clone()])),
+                Box::new(pawn.clone())
+            )
+        );
+        //set operations are read from left to right
+        assert_eq!(
+            parse_string("[0,1]^[1..2]&![0,1]&[0,1]").unwrap(),
+            MoveCompact::Intersection(
+                Box::new(MoveCompact::Difference(
+                    Box::new(pawn.clone()),
+                    Box::new(one.clone())
+                )),
+                Box::new(one.clone())
+            )
+        );
+
+        for spec in [
+            "{[1,0]^*,[1,1]^*}@8&![-1..1,-1..1]",
+            "[1,0]^*&[1,0]^*&![1,0]",
+            "[1,0]^*&!([1,0]&[1,0])",
+            "([1,0]^*&![1,0])|-/",
+            "[1,1]*([1,0]^*&![1,0]^2)",
+            "{[1,0]&[1,0]^2,[0,1]}",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert!(parse_string("[1,0]&").is_err());
+        assert!(parse_string("[1,0]&&[1,0]").is_err());
+    }
+
+    #[test]
+    fn locked_exponents() {
+        assert_eq!(
+            parse_string("[1,2]^!*").unwrap(),
+            MoveCompact::Modded(
+                Box::new(MoveCompact::Jump(Jump { x: 1, y: 2 })),
+                Mod::Locked(Box::new(Mod::ExponentiateInfinite(1)))
+            )
+        );
+        //modifiers are applied from left to right, so the brackets aren't needed
+        assert_eq!(
+            parse_string("([1,2]|-/)^!*").unwrap().notation(),
+            "[1,2]|-/^!*"
+        );
+        for spec in ["{[1,0],[0,1]}^!2", "[1,0]@8^![2..4]", "[1,0]@8^![0..*]"] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert!(parse_string("[1,0]^!").is_err());
+        assert!(parse_string("[1,0]^!!*").is_err());
+        assert!(parse_string("[1,0]^!?").is_err());
+    }
+
+    #[test]
+    fn unordered_sequences() {
+        let step = MoveCompact::Jump(Jump { x: 1, y: 0 });
+        let diagonal = MoveCompact::Jump(Jump { x: 1, y: 1 });
+        assert_eq!(
+            parse_string("[1,0] <> [1,1]").unwrap(),
+            MoveCompact::Unordered(vec![step.clone(), diagonal.clone()])
+        );
+        //<> binds tighter than a sequence
+        assert_eq!(
+            parse_string("[1,0]*[1,0]<>[1,1]").unwrap(),
+            MoveCompact::Sequence(vec![
+                step.clone(),
+                MoveCompact::Unordered(vec![step.clone(), diagonal.clone()])
+            ])
+        );
+
+        for spec in [
+            "[1,0]<>[1,1]<>[0,1]",
+            "([1,0]<>[1,1])|-/",
+            "[1,0]^*<>?[0,1]*[1,1]",
+            "([1,0]*[0,1])<>([1,0]<>[1,1])",
+            "[1,0]<>[1,1]&![1,0]*[1,1]",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert!(parse_string("[1,0]<[1,1]").is_err());
+        assert!(parse_string("[1,0]<>").is_err());
+    }
+
+    #[test]
+    fn relative_steps() {
+        assert_eq!(
+            parse_string("[1,2]*[L]").unwrap(),
+            MoveCompact::Sequence(vec![
+                MoveCompact::Jump(Jump { x: 1, y: 2 }),
+                MoveCompact::Turn(1)
+            ])
+        );
+        assert_eq!(parse_string("[RR]").unwrap(), MoveCompact::Turn(6));
+
+        for spec in ["[1,0]*[F]^*", "([1,2]*[L]^[0..6])|-/", "[1,1]*{[LL],[RR]}"] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert_eq!(parse_string("[RRRR]").unwrap().notation(), "[LLLL]");
+        assert_eq!(parse_string("[LLLLLLLLL]").unwrap().notation(), "[L]");
+        assert_eq!(parse_string("[RRRRRRRR]").unwrap().notation(), "[F]");
+
+        assert!(parse_string("[LR]").is_err());
+        assert!(parse_string("[FF]").is_err());
+        assert!(parse_string("[L,1]").is_err());
+        assert!(parse_string("?[L]").is_err());
+    }
+
+    #[test]
+    fn board_relative_bounds() {
+        assert_eq!(
+            parse_string("[1,0]^[1..w/2]").unwrap(),
+            MoveCompact::Modded(
+                Box::new(MoveCompact::Jump(Jump { x: 1, y: 0 })),
+                Mod::ExponentiateRange(Bound::Exactly(1), Bound::Width(2))
+            )
+        );
+        assert_eq!(
+            parse_string("?#[0,1]").unwrap(),
+            MoveCompact::Guard(Jump { x: 0, y: 1 }, TileCondition::Impassable)
+        );
+
+        for spec in [
+            "[1,0]^[0..h]",
+            "[1,1]|-^[2..w/3]",
+            "[1,0]@4^![e..e]",
+            "[0,1]^[e..4]",
+            "[1,0]^[1..e]",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        //board relative bounds are compared once the board is known
+        assert!(parse_string("[1,0]^[w..2]").is_ok());
+        assert_eq!(
+            parse_string("[1,0]^[1..w/0]"),
+            Err(ParsingError::DivisionByZero(at(13)))
+        );
+        assert_eq!(
+            parse_string("[1,0]^[e..*]"),
+            Err(ParsingError::UnboundedBoardRelative(at(11)))
+        );
+        assert!(parse_string("[1,0]^[1..X]").is_err());
+    }
+
+    #[test]
+    fn bouncing() {
+        assert_eq!(
+            parse_string("[1,1]^*%2").unwrap(),
+            MoveCompact::Modded(
+                Box::new(MoveCompact::Modded(
+                    Box::new(MoveCompact::Jump(Jump { x: 1, y: 1 })),
+                    Mod::ExponentiateInfinite(1)
+                )),
+                Mod::Bounce(Some(2))
+            )
+        );
+        for spec in ["[1,1]^*%|-", "[1,2]^*%0|-/", "{[1,0],[0,1]}^[2..4]%3"] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+        assert!(parse_string("[1,1]%-1").is_err());
+
+        //relative steps can't be bounced, even through a name, but can follow a bounce
+        let at = |column| Position { line: 1, column };
+        assert_eq!(
+            parse_string("([1,0]*[L])^*%"),
+            Err(ParsingError::TurnInBounce(at(14)))
+        );
+        assert_eq!(
+            parse_string("let $t = [F]; {[1,1],$t}|%1"),
+            Err(ParsingError::TurnInBounce(at(26)))
+        );
+        assert!(parse_string("[1,1]^*%*[L]").is_ok());
+    }
+
+    #[test]
+    fn named_moves() {
+        let queen =
+            parse_string("let $rook = [1,0]^*|-/; let $bishop = [1,1]^*|-; {$rook,$bishop}")
+                .unwrap();
+        assert_eq!(
+            queen,
+            MoveCompact::Choice(vec![
+                MoveCompact::Named(
+                    String::from("rook"),
+                    Box::new(parse_string("[1,0]^*|-/").unwrap())
+                ),
+                MoveCompact::Named(
+                    String::from("bishop"),
+                    Box::new(parse_string("[1,1]^*|-").unwrap())
+                ),
+            ])
+        );
+        assert_eq!(
+            queen.expanded(),
+            parse_string("{[1,0]^*|-/,[1,1]^*|-}").unwrap()
+        );
+
+        //the names can be kept, or written inline, bracketed as needed
+        let keep = NotationOptions {
+            keep_references: true,
+            ..Default::default()
+        };
+        assert_eq!(queen.notation(), "{[1,0]^*|-/,[1,1]^*|-}");
+        for spec in [
+            "let $rook = [1,0]^*|-/; let $bishop = [1,1]^*|-; {$rook,$bishop}",
+            "let $w = [1,0]; let $rider = $w^*; $rider|-*$w",
+            "let $a = [1,0]*[0,1]; $a|-",
+        ] {
+            let mov = parse_string(spec).unwrap();
+            assert_eq!(mov.notation_with(keep), spec);
+            assert_eq!(parse_string(&mov.notation_with(keep)).unwrap(), mov);
+            assert_eq!(parse_string(&mov.notation()).unwrap(), mov.expanded());
+        }
+        assert_eq!(
+            parse_string("let $a = [1,0]*[0,1]; $a|-")
+                .unwrap()
+                .notation(),
+            "([1,0]*[0,1])|-"
+        );
+
+        //names can also be bound by the caller
+        let mut bindings = Bindings::new();
+        bindings.insert("rook", parse_string("[1,0]^*|-/").unwrap());
+        let chancellor = MoveCompact::parse_with("{$rook,[1,2]|-/}", &bindings).unwrap();
+        assert_eq!(
+            chancellor.notation(),
+            parse_string("{[1,0]^*|-/,[1,2]|-/}").unwrap().notation()
+        );
+        assert_eq!(
+            chancellor.notation_with(keep),
+            "let $rook = [1,0]^*|-/; {$rook,[1,2]|-/}"
+        );
+        assert_eq!(
+            parse_string(&chancellor.notation_with(keep)).unwrap(),
+            chancellor
+        );
+
+        //a bound move may have been read with a different move bound to a name than the one it is bound alongside,
+        //so the names are written distinctly
+        let mut inner = Bindings::new();
+        inner.insert("x", parse_string("[0,1]").unwrap());
+        let mut outer = Bindings::new();
+        outer.insert("x", parse_string("[1,0]").unwrap());
+        outer.insert("y", MoveCompact::parse_with("$x", &inner).unwrap());
+        let both = MoveCompact::parse_with("{$x,$y,$x}", &outer).unwrap();
+        assert_eq!(
+            both.notation_with(keep),
+            "let $x = [1,0]; let $x_2 = [0,1]; let $y = $x_2; {$x,$y,$x}"
+        );
+        assert_eq!(
+            parse_string(&both.notation_with(keep)).unwrap().expanded(),
+            both.expanded()
+        );
+        assert_eq!(
+            both.expanded(),
+            parse_string("{[1,0],[0,1],[1,0]}").unwrap()
+        );
+
+        assert_eq!(
+            parse_string("$queen"),
+            Err(ParsingError::UndefinedName(String::from("queen"), at(2)))
+        );
+        assert_eq!(
+            parse_string("let $a=[1,0];let $a=[0,1];$a"),
+            Err(ParsingError::AlreadyDefined(String::from("a"), at(19)))
+        );
+        assert_eq!(
+            MoveCompact::parse_with("let $rook=[1,0]; $rook", &bindings),
+            Err(ParsingError::AlreadyDefined(String::from("rook"), at(6)))
+        );
+        assert!(parse_string("let $a=[1,0]").is_err());
+        assert!(parse_string("let a=[1,0];a").is_err());
+        assert!(parse_string("{$a,let $a=[1,0];$a}").is_err());
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            parse_string("[m,-n]^k").unwrap(),
+            MoveCompact::Modded(
+                Box::new(MoveCompact::JumpTemplate(
+                    Component::Parameter(String::from("m")),
+                    Component::Negated(String::from("n"))
+                )),
+                Mod::ExponentiateParameter(String::from("k"))
+            )
+        );
+        assert_eq!(
+            parse_string("[1,0]^[1..k]").unwrap(),
+            MoveCompact::Modded(
+                Box::new(MoveCompact::Jump(Jump { x: 1, y: 0 })),
+                Mod::ExponentiateRange(Bound::Exactly(1), Bound::Parameter(String::from("k")))
+            )
+        );
+
+        for spec in [
+            "[m,n]|-/",
+            "[m,1]^*",
+            "[-m,0]@4^![k..w]",
+            "[1,1]^[lo..hi]",
+            "[step_1,0]^![2..k]",
+            "{[m,n],[n,m]}",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+
+        assert_eq!(
+            parse_string("[1..m,0]"),
+            Err(ParsingError::ParameterInJumpRange)
+        );
+        assert_eq!(
+            parse_string("[1..2,m]"),
+            Err(ParsingError::ParameterInJumpRange)
+        );
+        assert_eq!(
+            parse_string("[1,0]^w"),
+            Err(ParsingError::ReservedParameter(String::from("w"), at(7)))
+        );
+        assert_eq!(
+            parse_string("[1,0]^[k..*]"),
+            Err(ParsingError::UnboundedBoardRelative(at(11)))
+        );
+        assert!(parse_string("[M,1]").is_err());
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            parse_string("#double([0,1]^2)").unwrap(),
+            MoveCompact::Labelled(
+                String::from("double"),
+                Box::new(MoveCompact::Modded(
+                    Box::new(MoveCompact::Jump(Jump { x: 0, y: 1 })),
+                    Mod::Exponentiate(2)
+                ))
+            )
+        );
+
+        for spec in [
+            "{[0,1]~,#double([0,1]^2~),#take([1,1]|!)}",
+            "#ride([1,0]^*)|-/",
+            "#leg_1([1,0]*[0,1])*#leg_2({[1,0],[0,1]})",
+            "#outer(#inner([1,1])^*)",
+            "#r([1,0]^*|-/&[1,0]^[1..2]|-/)",
+            "?#[1,0]*#a([1,0])",
+        ] {
+            assert_eq!(parse_string(spec).unwrap().notation(), spec);
+        }
+
+        assert_eq!(
+            parse_string("#([1,0])"),
+            Err(ParsingError::ExpectedCharacter(vec!["name"], '(', at(2)))
+        );
+        assert_eq!(
+            parse_string("#a[1,0]"),
+            Err(ParsingError::ExpectedCharacter(vec!["("], '[', at(3)))
+        );
+        assert_eq!(parse_string("#a([1,0]"), Err(ParsingError::UnexpectedEOF));
+
+        //the right of a set operation makes no jumps of its own, so its labels could never be reported
+        assert_eq!(
+            parse_string("[1,0]^*&#short([1,0]^[1..2])"),
+            Err(ParsingError::LabelInSetOperand(at(9)))
+        );
+        assert_eq!(
+            parse_string("[1,0]^*&!([1,0]^2*#a([1,0]))"),
+            Err(ParsingError::LabelInSetOperand(at(10)))
+        );
+        assert_eq!(
+            parse_string("let $a = #a([1,0]); [1,0]^*&$a"),
+            Err(ParsingError::LabelInSetOperand(at(29)))
+        );
+        assert!(parse_string("#a([1,0]^*)&[1,0]").is_ok());
     }
 }
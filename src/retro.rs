@@ -0,0 +1,902 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use petgraph::graph::{DefaultIx, NodeIndex};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeIndexable};
+use petgraph::Direction;
+
+use crate::movespec::{resolve, turn, OnBoard, Resolved};
+use crate::{
+    leaves_board, step, Board, EdgeType, Jump, MoveGraph, MoveTrace, Moves, OrientedGraph,
+    ReadTracker, Shot, State, TileCondition, TileState, Trace, Vacated,
+};
+
+/**
+A `MoveGraph` with its edges flipped and its jumps negated, for searching backwards from the position a move finishes on.
+A forward move starts at a single head, but can finish at any of several nodes; so the reversed graph starts at all of these,
+and can stop at any node that the forward move could reach without making a jump.
+*/
+#[derive(Debug, Clone)]
+pub struct ReverseMoveGraph {
+    pub graph: petgraph::stable_graph::StableDiGraph<(), EdgeType, DefaultIx>,
+    heads: Vec<NodeIndex<DefaultIx>>,
+    tails: HashSet<NodeIndex<DefaultIx>>,
+    //where a shot lands can't be found backwards, so shots are searched forwards, from where they return to
+    //for the source of each shot's end, the (source, target) of the edges that could have started it
+    shots: HashMap<NodeIndex<DefaultIx>, Vec<Edge>>,
+    //the graph the shots are searched on, if there are any
+    forward: Option<MoveGraph>,
+    //if the move has exponents that depend on the size of the board, the graph it was reversed from,
+    //and the reversed graphs of that for each size of board
+    board_relative: Option<(MoveGraph, Resolved<ReverseMoveGraph>)>,
+}
+
+type Edge = (NodeIndex<DefaultIx>, NodeIndex<DefaultIx>);
+
+type Leg = (i32, i32);
+
+impl From<&MoveGraph> for ReverseMoveGraph {
+    fn from(piece: &MoveGraph) -> Self {
+        let mut graph = piece.graph.clone();
+        graph.reverse();
+        for e in graph.edge_weights_mut() {
+            match e {
+                EdgeType::Jump(j) => *e = EdgeType::Jump(Jump { x: -j.x, y: -j.y }),
+                EdgeType::PhasingJump(j) => *e = EdgeType::PhasingJump(Jump { x: -j.x, y: -j.y }),
+                _ => (),
+            }
+        }
+
+        let forward = OrientedGraph::new(piece, false, false);
+        let heads = piece
+            .graph
+            .node_indices()
+            .filter(|n| forward.can_finish[n.index()])
+            .collect();
+
+        //the nodes reachable from the head by dummy edges and labels alone
+        let mut tails = HashSet::from([piece.head()]);
+        let mut to_visit = vec![piece.head()];
+        while let Some(n) = to_visit.pop() {
+            for e in piece.outgoing_edges(n) {
+                match e.weight() {
+                    EdgeType::Jump(_)
+                    | EdgeType::PhasingJump(_)
+                    | EdgeType::Condition(_)
+                    | EdgeType::Hurdle
+                    | EdgeType::Capture
+                    | EdgeType::Guard(_, _)
+                    | EdgeType::Bounce(_, _, _)
+                    | EdgeType::ShotStart
+                    | EdgeType::ShotEnd
+                    | EdgeType::Turn(_) => (),
+                    EdgeType::DummyOptional | EdgeType::DummyRequired | EdgeType::Label(_) => {
+                        if tails.insert(e.target()) {
+                            to_visit.push(e.target());
+                        }
+                    }
+                }
+            }
+        }
+
+        //the nodes inside a shot can only be reached through its start
+        let mut shots: HashMap<_, Vec<_>> = HashMap::new();
+        for end in piece.graph.edge_references() {
+            if *end.weight() != EdgeType::ShotEnd {
+                continue;
+            }
+            let mut seen = HashSet::from([end.source()]);
+            let mut to_visit = vec![end.source()];
+            while let Some(n) = to_visit.pop() {
+                for e in piece.graph.edges_directed(n, Direction::Incoming) {
+                    if *e.weight() == EdgeType::ShotStart {
+                        shots
+                            .entry(end.source())
+                            .or_default()
+                            .push((e.source(), e.target()));
+                    } else if seen.insert(e.source()) {
+                        to_visit.push(e.source());
+                    }
+                }
+            }
+        }
+        let forward = match shots.is_empty() {
+            true => None,
+            false => Some(piece.clone()),
+        };
+
+        ReverseMoveGraph {
+            graph,
+            heads,
+            tails,
+            shots,
+            forward,
+            board_relative: match piece.is_board_relative() {
+                true => Some((piece.clone(), Resolved::default())),
+                false => None,
+            },
+        }
+    }
+}
+
+impl From<MoveGraph> for ReverseMoveGraph {
+    fn from(input: MoveGraph) -> Self {
+        (&input).into()
+    }
+}
+
+/// A ReverseMoveGraph with the inversions applied to its jumps, flattened into a table indexed by node
+struct OrientedReverse {
+    outgoing: Vec<Vec<(NodeIndex<DefaultIx>, EdgeType)>>,
+    //every leg that a relative step could have been made along: every jump of the piece, turned in every way
+    headings: Vec<Leg>,
+}
+
+impl ReverseMoveGraph {
+    /// The graph to search on the board, reversed from the forward graph built for the size of the board
+    /// if the move has exponents that depend on it
+    fn on_board<B>(&self, board: &B) -> OnBoard<'_, ReverseMoveGraph>
+    where
+        B: Board,
+    {
+        match &self.board_relative {
+            Some((piece, resolved)) => {
+                OnBoard::Resolved(resolve(resolved, board.dimensions(), || {
+                    ReverseMoveGraph::from(&*piece.on_board(board))
+                }))
+            }
+            None => OnBoard::Borrowed(self),
+        }
+    }
+
+    fn oriented(&self, invert_x: bool, invert_y: bool) -> OrientedReverse {
+        let outgoing: Vec<Vec<_>> = (0..self.graph.node_bound())
+            .map(|i| {
+                self.graph
+                    .edges(NodeIndex::new(i))
+                    .map(|e| (e.target(), e.weight().inverted(invert_x, invert_y)))
+                    .collect()
+            })
+            .collect();
+
+        let mut headings = Vec::new();
+        let edges = || outgoing.iter().flatten().map(|(_, e)| e);
+        if edges().any(|e| matches!(e, EdgeType::Turn(_))) {
+            for e in edges() {
+                if let EdgeType::Jump(j) | EdgeType::PhasingJump(j) = e {
+                    for eighths in 0..8 {
+                        //the jumps of the reversed graph are the other way to the legs the piece made
+                        let leg = turn(Jump { x: -j.x, y: -j.y }, eighths);
+                        if !headings.contains(&(leg.x, leg.y)) {
+                            headings.push((leg.x, leg.y));
+                        }
+                    }
+                }
+            }
+        }
+
+        OrientedReverse { outgoing, headings }
+    }
+
+    fn roots(&self, target_position: (i32, i32)) -> Vec<MoveTrace<DefaultIx>> {
+        self.heads
+            .iter()
+            .map(|h| MoveTrace::root(*h, target_position))
+            .collect()
+    }
+}
+
+type SeenStates = HashMap<State, Rc<Trace<(i32, i32)>>>;
+
+/**
+The reverse of `check_move`: finds every position from which the piece could legally move to target_position,
+along with the path it would take (starting at that position, as returned by `check_move`).
+As with `check_move`, neither the target position nor the start positions are checked against the board,
+so a position holding a piece is returned if that piece could make the move.
+*/
+pub fn retro_moves<B>(
+    piece: &ReverseMoveGraph,
+    board: &B,
+    target_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> Moves
+where
+    B: Board,
+{
+    let piece = piece.on_board(board);
+    let piece = &*piece;
+    let outgoing = piece.oriented(invert_x, invert_y);
+    let forward = piece
+        .forward
+        .as_ref()
+        .map(|f| OrientedGraph::new(f, invert_x, invert_y));
+
+    //the piece's start is empty for the whole of its move, but which position that is isn't known until it is found.
+    //searching the board as it is finds the starts that are empty; a start that isn't can only have been found by reading it,
+    //so each other position the search read is emptied in turn, and searched for again
+    let tracker = ReadTracker::new(board);
+    let mut starts = search_back(
+        piece,
+        &outgoing,
+        forward.as_ref(),
+        &tracker,
+        target_position,
+    );
+    starts.retain(|start, _| board.tile_at(*start) == TileState::Empty);
+    for position in tracker.into_reads() {
+        if board.tile_at(position) != TileState::Empty {
+            let vacated = Vacated {
+                board,
+                start_position: position,
+            };
+            let found = search_back(
+                piece,
+                &outgoing,
+                forward.as_ref(),
+                &vacated,
+                target_position,
+            );
+            if let Some(path) = found.get(&position) {
+                starts.insert(position, path.clone());
+            }
+        }
+    }
+
+    starts
+}
+
+/// Every position from which a search backwards from the target reaches a start of the move, on the board as given
+fn search_back<B>(
+    piece: &ReverseMoveGraph,
+    outgoing: &OrientedReverse,
+    forward: Option<&OrientedGraph>,
+    board: &B,
+    target_position: (i32, i32),
+) -> Moves
+where
+    B: Board,
+{
+    let mut starts: Moves = HashMap::new();
+    let mut record = |position: (i32, i32), trace: &Rc<Trace<(i32, i32)>>| {
+        starts
+            .entry(position)
+            .or_insert_with(|| backward_path(position, trace));
+    };
+
+    let mut traces = piece.roots(target_position);
+    let mut visited: HashSet<State> = HashSet::new();
+
+    while let Some(head) = traces.pop() {
+        if !visited.insert(head.state()) {
+            continue;
+        }
+
+        //the piece could have started here, and reached this point without jumping
+        //(and so without making a leg that a later relative step turned from)
+        if piece.tails.contains(&head.current_move) && head.heading.is_none() {
+            record(head.current_position, &head.trace);
+        }
+
+        step_back(
+            piece,
+            outgoing,
+            forward,
+            board,
+            &head,
+            &mut traces,
+            &mut record,
+        );
+    }
+
+    starts
+}
+
+/**
+Equivalent to `check_move`, but searches from both ends of the move at once: forwards from the start position on the piece's graph,
+and backwards from the target position on its reversed graph, until the two searches meet.
+For pieces whose moves fan out widely (such as long multi-leg riders) on large boards, this can visit far fewer states;
+for small pieces the cost of preparing both graphs outweighs this.
+`reverse` must be the reverse of `piece`.
+*/
+pub fn check_move_bidirectional<B>(
+    piece: &MoveGraph,
+    reverse: &ReverseMoveGraph,
+    board: &B,
+    start_position: (i32, i32),
+    target_position: (i32, i32),
+    invert_x: bool,
+    invert_y: bool,
+) -> Option<Vec<(i32, i32)>>
+where
+    B: Board,
+{
+    let reverse = reverse.on_board(board);
+    let reverse = &*reverse;
+    let forward_graph = OrientedGraph::new(&piece.on_board(board), invert_x, invert_y);
+    let backward_outgoing = reverse.oriented(invert_x, invert_y);
+    let board = &Vacated {
+        board,
+        start_position,
+    };
+
+    //the traces that reached each state; the states of one search are checked against those of the other
+    let mut forward_seen: SeenStates = HashMap::new();
+    let mut backward_seen: SeenStates = HashMap::new();
+    //the forward states by everything but their heading, for backward states that don't yet know which leg was made last
+    let mut forward_unturned: SeenStates = HashMap::new();
+
+    //each search advances by one layer at a time, the smaller one first, so that they meet roughly in the middle
+    let mut forward_layer = vec![MoveTrace::root(forward_graph.head, start_position)];
+    let mut backward_layer = reverse.roots(target_position);
+
+    //if either search is exhausted without meeting the other, then it alone has shown there is no path
+    while !forward_layer.is_empty() && !backward_layer.is_empty() {
+        if forward_layer.len() <= backward_layer.len() {
+            let mut next = Vec::new();
+            for head in forward_layer {
+                let state = head.state();
+                if forward_graph.can_finish[state.1.index()] && state.0 == target_position {
+                    return Some(Vec::<(i32, i32)>::from(Trace::Node(state.0, head.trace)));
+                }
+                if forward_seen.contains_key(&state) {
+                    continue;
+                }
+                //a backward trace that leaves an occupied tile can only meet a forward one that is allowed to
+                //and one that knows the last leg can only meet a forward one that made it
+                let may_leave = head.passing || matches!(*head.trace, Trace::Root);
+                let back = [false, true]
+                    .into_iter()
+                    .filter(|passing| !passing || may_leave)
+                    .flat_map(|passing| {
+                        [None, state.4].map(|h| (state.0, state.1, passing, state.3, h))
+                    })
+                    .find_map(|key| backward_seen.get(&key));
+                if let Some(back) = back {
+                    return Some(join_paths(state.0, &head.trace, back));
+                }
+                forward_seen.insert(state, head.trace.clone());
+                forward_unturned
+                    .entry((state.0, state.1, state.2, state.3, None))
+                    .or_insert_with(|| head.trace.clone());
+                step(&forward_graph, board, &head, &mut next);
+            }
+            forward_layer = next;
+        } else {
+            let mut next = Vec::new();
+            let mut found = None;
+            for head in backward_layer {
+                let state = head.state();
+                if backward_seen.contains_key(&state) {
+                    continue;
+                }
+                if reverse.tails.contains(&state.1)
+                    && state.0 == start_position
+                    && state.4.is_none()
+                {
+                    return Some(backward_path(state.0, &head.trace));
+                }
+                let front = [true, false]
+                    .into_iter()
+                    .filter(|passing| *passing || !state.2)
+                    .find_map(|passing| {
+                        let key = (state.0, state.1, passing, state.3, state.4);
+                        match state.4 {
+                            Some(_) => forward_seen.get(&key),
+                            None => forward_unturned.get(&key),
+                        }
+                    });
+                if let Some(front) = front {
+                    return Some(join_paths(state.0, front, &head.trace));
+                }
+                backward_seen.insert(state, head.trace.clone());
+                step_back(
+                    reverse,
+                    &backward_outgoing,
+                    Some(&forward_graph),
+                    board,
+                    &head,
+                    &mut next,
+                    &mut |position, trace| {
+                        if position == start_position && found.is_none() {
+                            found = Some(backward_path(position, trace));
+                        }
+                    },
+                );
+                if found.is_some() {
+                    return found;
+                }
+            }
+            backward_layer = next;
+        }
+    }
+
+    None
+}
+
+/// Pushes the traces that lead backwards into head, by taking each reversed edge of its node.
+/// Calls record with any position the piece could have started at, but that the search cannot continue from.
+fn step_back<B, F>(
+    piece: &ReverseMoveGraph,
+    graph: &OrientedReverse,
+    forward: Option<&OrientedGraph>,
+    board: &B,
+    head: &MoveTrace<DefaultIx>,
+    traces: &mut Vec<MoveTrace<DefaultIx>>,
+    record: &mut F,
+) where
+    B: Board,
+    F: FnMut((i32, i32), &Rc<Trace<(i32, i32)>>),
+{
+    let tile = board.tile_at(head.current_position);
+
+    for (n, e) in &graph.outgoing[head.current_move.index()] {
+        match e {
+            EdgeType::Jump(_) | EdgeType::PhasingJump(_) | EdgeType::Turn(_) => {
+                //the forward move jumped from here to the current position
+                //unless it was phasing, it is not passing through the current position, so it can't have been blocked by it
+                if head.passing && !matches!(e, EdgeType::PhasingJump(_)) {
+                    continue;
+                }
+                for (leg, heading) in legs_back(e, head.heading, &graph.headings) {
+                    let from = (
+                        head.current_position.0 - leg.0,
+                        head.current_position.1 - leg.1,
+                    );
+                    let trace = Rc::new(Trace::Node(head.current_position, head.trace.clone()));
+                    match departure(board.tile_at(from), false) {
+                        Some(passing) => traces.push(MoveTrace {
+                            trace,
+                            passing,
+                            heading,
+                            ..MoveTrace::root(*n, from)
+                        }),
+                        None => {
+                            if piece.tails.contains(n) && heading.is_none() {
+                                record(from, &trace);
+                            }
+                        }
+                    }
+                }
+            }
+            EdgeType::DummyOptional | EdgeType::DummyRequired | EdgeType::Label(_) => {
+                traces.push(head.follow(*n))
+            }
+            EdgeType::Condition(c) => {
+                if c.holds(tile) {
+                    traces.push(head.follow(*n))
+                }
+            }
+            EdgeType::Hurdle | EdgeType::Capture => {
+                let condition = match e {
+                    EdgeType::Capture => TileCondition::Enemy,
+                    _ => TileCondition::Occupied,
+                };
+                if condition.holds(tile) {
+                    traces.push(MoveTrace {
+                        passing: false,
+                        ..head.follow(*n)
+                    })
+                }
+            }
+            EdgeType::Guard(offset, c) => {
+                let guarded = (
+                    head.current_position.0 + offset.x,
+                    head.current_position.1 + offset.y,
+                );
+                if c.holds(board.tile_at(guarded)) {
+                    traces.push(head.follow(*n))
+                }
+            }
+            EdgeType::Bounce(step, flip_x, flip_y) => {
+                //the forward move bounced here, before making the step
+                if leaves_board(board, head.current_position, *step) == Some((*flip_x, *flip_y)) {
+                    traces.push(head.follow(*n))
+                }
+            }
+            EdgeType::ShotEnd => {
+                //the piece shot from here, and the shot may have started at any of the shot's starts
+                //the piece left here when it shot, so it must not have been blocked here, as for a jump
+                let forward = match forward {
+                    Some(f) => f,
+                    None => continue,
+                };
+                let passing = match departure(tile, head.passing) {
+                    Some(passing) => passing,
+                    None => {
+                        if piece.shots[n].iter().any(|(before, start)| {
+                            piece.tails.contains(before) && shot_hits(forward, board, head, *start)
+                        }) {
+                            record(head.current_position, &head.trace);
+                        }
+                        continue;
+                    }
+                };
+                for (before, start) in &piece.shots[n] {
+                    if shot_hits(forward, board, head, *start) {
+                        traces.push(MoveTrace {
+                            passing,
+                            ..head.follow(*before)
+                        })
+                    }
+                }
+            }
+            //the backwards search never enters a shot, so never reaches its start
+            EdgeType::ShotStart => (),
+        }
+    }
+}
+
+/**
+The legs the forward move could have made along a reversed edge, given the leg it must have made last, if that is known.
+Each comes with the leg the move must have made before it, if that is known.
+*/
+fn legs_back(edge: &EdgeType, heading: Option<Leg>, headings: &[Leg]) -> Vec<(Leg, Option<Leg>)> {
+    match edge {
+        //the jumps of the reversed graph are the other way to the legs the piece made
+        EdgeType::Jump(j) | EdgeType::PhasingJump(j) => match heading {
+            Some(h) if h != (-j.x, -j.y) => Vec::new(),
+            _ => vec![((-j.x, -j.y), None)],
+        },
+        //if the leg isn't known, it could have been along any of the legs that relative steps can be made along
+        EdgeType::Turn(eighths) => match heading {
+            Some(h) => vec![h],
+            None => headings.to_vec(),
+        }
+        .into_iter()
+        .map(|(x, y)| {
+            let before = turn(Jump { x, y }, (8 - eighths) % 8);
+            ((x, y), Some((before.x, before.y)))
+        })
+        .collect(),
+        //the other edges don't move the piece
+        _ => Vec::new(),
+    }
+}
+
+/**
+The obligation a backwards trace takes on when the piece left a tile in the given state, given the one it had already:
+whether the piece must have been passing through the tile. If the piece could only have left the tile at the start of the move
+(e.g it is off the board), there is nothing to continue with, and this is None.
+*/
+fn departure(tile: TileState, passing: bool) -> Option<bool> {
+    match tile {
+        TileState::Empty => Some(passing),
+        TileState::Friendly | TileState::Enemy => Some(true),
+        TileState::Impassable => None,
+    }
+}
+
+/// Whether the piece, where head is, could have made the shot starting at start and come back to head's node
+fn shot_hits<B>(
+    forward: &OrientedGraph,
+    board: &B,
+    head: &MoveTrace<DefaultIx>,
+    start: NodeIndex<DefaultIx>,
+) -> bool
+where
+    B: Board,
+{
+    let origin = head.current_position;
+    //the root trace means the shot is never blocked by the piece's own tile; this was checked by the caller
+    let mut traces = vec![MoveTrace {
+        shot: Some(Rc::new(Shot {
+            origin,
+            trace: Rc::new(Trace::Root),
+            passing: false,
+            heading: None,
+        })),
+        ..MoveTrace::root(start, origin)
+    }];
+    let mut visited: HashSet<State> = HashSet::new();
+
+    while let Some(shot) = traces.pop() {
+        if shot.current_move == head.current_move && shot.shot.is_none() {
+            return true;
+        }
+        if !visited.insert(shot.state()) {
+            continue;
+        }
+        step(forward, board, &shot, &mut traces);
+    }
+
+    false
+}
+
+/// The path from position to the target, given the trace a backwards search took to reach position
+fn backward_path(position: (i32, i32), trace: &Rc<Trace<(i32, i32)>>) -> Vec<(i32, i32)> {
+    //the trace is built from the target backwards, so it must be flipped to give the path
+    let mut path = Vec::<(i32, i32)>::from(Trace::Node(position, trace.clone()));
+    path.reverse();
+    path
+}
+
+/// The full path through a state that the forwards and backwards searches both reached
+fn join_paths(
+    position: (i32, i32),
+    forward: &Rc<Trace<(i32, i32)>>,
+    backward: &Rc<Trace<(i32, i32)>>,
+) -> Vec<(i32, i32)> {
+    let mut path = Vec::<(i32, i32)>::from(Trace::Node(position, forward.clone()));
+    path.extend(backward_path(position, backward).into_iter().skip(1));
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        check_move, check_move_bidirectional, retro_moves, MoveCompact, MoveGraph, ReverseMoveGraph,
+    };
+
+    struct PiecesBoard {
+        pieces: Vec<(i32, i32)>,
+        enemies: Vec<(i32, i32)>,
+    }
+
+    impl crate::Board for PiecesBoard {
+        fn tile_at(&self, position: (i32, i32)) -> crate::TileState {
+            if position.0 < 0 || position.0 > 11 || position.1 < 0 || position.1 > 11 {
+                crate::TileState::Impassable
+            } else if self.pieces.contains(&position) {
+                crate::TileState::Friendly
+            } else if self.enemies.contains(&position) {
+                crate::TileState::Enemy
+            } else {
+                crate::TileState::Empty
+            }
+        }
+
+        fn dimensions(&self) -> Option<(i32, i32)> {
+            Some((12, 12))
+        }
+    }
+
+    const RETRO_SPECS: &[&str] = &[
+        "[1,0]^*|-/",
+        "[1,2]^*|-/",
+        "[1,2]|-/*[0,1]?",
+        "([2,2]^[2..*]-|/*[0,-4])^*",
+        "{[1,0]/,[1,1]}|-^*",
+        "[0,1]^[0..2]",
+        "{[0,1]~,[1,1]|!}",
+        "{[1,1]^*|-!,[1,0]^*|-/~}",
+        "{[1,0]^*|-/~,([1,0]^*:*[1,0]^*!)|-/}",
+        "([1,1]^*:*[1,1])|-",
+        "([1,0]^*:*[1,0]^*)|-/",
+        "(?[0,1]*[1,2])|-/",
+        "{?:[1,0]*[0,1]^*,?![1,0]*[1,1]^*|-}",
+        "([1,0]^*=)|-/",
+        "{[1,1]=*[1,0]^*|-,([1,0]^2=*[0,1])|-/}",
+        "(([1,1]+*[1,1]~)|-)^*",
+        "([1,0]+*[-1,0])|-/",
+        "{[1,0]|-/~,([1,0]^[1..3]>)|-/}",
+        "([0,1]^2>*[1,0]^*)|-/",
+        "{[1,1]*[1,0]^[0..*],[1,1]*[0,1]^[0..*]}|-",
+        "([1,2]^[0..*])|-/",
+        "{[1,0]^*,[1,1]^*}@8&![-1..1,-1..1]",
+        "([1,0]^*&!([1,0]^2)^*)|-/",
+        "[1,0]^*@4&[1,0]^[2..3]@4",
+        "(([1,1]+*[1,1]~)|-)^*&!(([1,1]+*[1,1]~)|-)^2",
+        "([1,2]|-/)^!*",
+        "({[1,0]*[0,1],[1,1]}|-)^![0..*]",
+        "([1,0]<>[1,1])|-/",
+        "([1,0]^*<>[0,1]^*<>[1,1])|-",
+        "([1,2]*[L]^[0..6])|-/",
+        "([1,1]*([RR]*[LL])^[0..*]*[RR]?)|-",
+        "{[1,0],[1,1]}@8*[F]^[0..*]",
+        "([0,1]*[1,0]^2>*[F]^[0..2])|-/",
+        "([1,0]=*[L]^*)|-",
+        "[1,0]@4^![e..e]",
+        "([1,1]^[1..w/3])|-",
+        "{[1,0]^[e..2],[0,1]^[1..h/4]}|-",
+        "[1,1]^*%|-",
+        "[1,2]^*%2|-/",
+        "([1,0]^2*[0,1])^[1..4]%1",
+        "[1,0]^*%|-/",
+        "([1,1]*[-1,-1]^2)|-",
+        "{#step([0,1]~),#double([0,1]^2~),#take([1,1]|!)}",
+        "(#ride([1,0]^*)*#turn([0,1]))|-/",
+    ];
+
+    const BIDIRECTIONAL_SPECS: &[&str] = &[
+        "[1,2]|-/",
+        "[1,2]^*|-/",
+        "[1,2]|-/*[0,1]?",
+        "([2,2]^[2..*]-|/*[0,-4])^*",
+        "{[1,0]/,[1,1]}|-^*",
+        "[0,1]^[0..2]",
+        "{[1,1]^*|-!,[1,0]^*|-/~}",
+        "{[1,0]^*|-/~,([1,0]^*:*[1,0]^*!)|-/}",
+        "([1,0]^*:*[1,0]^*)|-/",
+        "(?[0,1]*[1,2])|-/",
+        "{?:[1,0]*[0,1]^*,?![1,0]*[1,1]^*|-}",
+        "([1,0]^*=)|-/",
+        "{[1,1]=*[1,0]^*|-,([1,0]^2=*[0,1])|-/}",
+        "(([1,1]+*[1,1]~)|-)^*",
+        "([0,1]^2>*[1,0]^*)|-/",
+        "{[1,0]^*,[1,1]^*}@8&![-1..1,-1..1]",
+        "([1,0]^*&!([1,0]^2)^*)|-/",
+        "([1,2]*[L]^[0..6])|-/",
+        "([0,1]*[1,0]^2>*[F]^[0..2])|-/",
+        "([1,0]^[e..e]*[0,1]^[0..h/2])|-/",
+        "[1,1]^*%3|-",
+        "([1,1]*[-1,-1]^2)|-",
+        "(#ride([1,0]^*)*#turn([0,1]))|-/",
+    ];
+
+    fn board() -> PiecesBoard {
+        PiecesBoard {
+            pieces: vec![(5, 5), (3, 4), (7, 7), (4, 9), (9, 3), (6, 2), (2, 2)],
+            enemies: vec![(5, 6), (6, 5), (8, 8)],
+        }
+    }
+
+    //every start retro_moves finds must be one check_move accepts, and no others
+    fn assert_retro_matches(spec: &str) {
+        let board = &board();
+        let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+        let reverse = &ReverseMoveGraph::from(piece);
+
+        for target in [(5, 5), (4, 4), (6, 3), (9, 8), (8, 8), (5, 6)] {
+            for (invert_x, invert_y) in [(false, false), (true, true)] {
+                let starts = retro_moves(reverse, board, target, invert_x, invert_y);
+                for x in -1..=12 {
+                    for y in -1..=12 {
+                        let forward = check_move(piece, board, (x, y), target, invert_x, invert_y);
+                        assert_eq!(
+                            starts.contains_key(&(x, y)),
+                            forward.is_some(),
+                            "{} from {:?} to {:?}",
+                            spec,
+                            (x, y),
+                            target
+                        );
+                    }
+                }
+
+                for (start, path) in starts {
+                    assert_eq!(path.first(), Some(&start));
+                    assert_eq!(path.last(), Some(&target));
+                }
+            }
+        }
+    }
+
+    //check_move_bidirectional must agree with check_move on every target
+    fn assert_bidirectional_matches(spec: &str) {
+        let board = &board();
+        let piece = &MoveGraph::from(spec.parse::<MoveCompact>().unwrap());
+        let reverse = &ReverseMoveGraph::from(piece);
+
+        for start in [(7, 3), (4, 4), (2, 2)] {
+            for x in -1..=12 {
+                for y in -1..=12 {
+                    let forward = check_move(piece, board, start, (x, y), false, true);
+                    let both =
+                        check_move_bidirectional(piece, reverse, board, start, (x, y), false, true);
+                    assert_eq!(
+                        forward.is_some(),
+                        both.is_some(),
+                        "{} from {:?} to {:?}",
+                        spec,
+                        start,
+                        (x, y)
+                    );
+                    if let Some(path) = both {
+                        assert_eq!(path.first(), Some(&start));
+                        assert_eq!(path.last(), Some(&(x, y)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_check_move() {
+        for spec in [
+            "[1,0]^*|-/",
+            "[1,2]|-/*[0,1]?",
+            "{[1,0]^*|-/~,([1,0]^*:*[1,0]^*!)|-/}",
+            "{?:[1,0]*[0,1]^*,?![1,0]*[1,1]^*|-}",
+            "(([1,1]+*[1,1]~)|-)^*",
+            "([0,1]^2>*[1,0]^*)|-/",
+            "([1,0]^*&!([1,0]^2)^*)|-/",
+            "({[1,0]*[0,1],[1,1]}|-)^![0..*]",
+            "([1,0]<>[1,1])|-/",
+            "([1,1]*([RR]*[LL])^[0..*]*[RR]?)|-",
+            "[1,0]@4^![e..e]",
+            "[1,2]^*%2|-/",
+            "([1,1]*[-1,-1]^2)|-",
+            "(#ride([1,0]^*)*#turn([0,1]))|-/",
+        ] {
+            assert_retro_matches(spec);
+        }
+    }
+
+    //the full sweep takes minutes, so it's only run on request
+    #[test]
+    #[ignore]
+    fn matches_check_move_exhaustive() {
+        for spec in RETRO_SPECS {
+            assert_retro_matches(spec);
+        }
+    }
+
+    #[test]
+    fn blocked_rook() {
+        let rook = &ReverseMoveGraph::from(MoveGraph::from(
+            "[1,0]^*|-/".parse::<MoveCompact>().unwrap(),
+        ));
+        let board = &PiecesBoard {
+            pieces: vec![(4, 2), (4, 6), (1, 4)],
+            enemies: vec![],
+        };
+
+        let mut starts: Vec<(i32, i32)> = retro_moves(rook, board, (4, 4), false, false)
+            .into_keys()
+            .filter(|(x, y)| (0..=11).contains(x) && (0..=11).contains(y))
+            .collect();
+        starts.sort();
+
+        //the pieces on (4,2),(4,6) and (1,4) could move there, but nothing behind them
+        assert_eq!(
+            starts,
+            vec![
+                (1, 4),
+                (2, 4),
+                (3, 4),
+                (4, 2),
+                (4, 3),
+                (4, 5),
+                (4, 6),
+                (5, 4),
+                (6, 4),
+                (7, 4),
+                (8, 4),
+                (9, 4),
+                (10, 4),
+                (11, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn bidirectional() {
+        for spec in [
+            "[1,2]^*|-/",
+            "([2,2]^[2..*]-|/*[0,-4])^*",
+            "{[1,0]^*|-/~,([1,0]^*:*[1,0]^*!)|-/}",
+            "(([1,1]+*[1,1]~)|-)^*",
+            "([0,1]^2>*[1,0]^*)|-/",
+            "([1,0]^*&!([1,0]^2)^*)|-/",
+            "([1,0]^[e..e]*[0,1]^[0..h/2])|-/",
+            "([1,1]*[-1,-1]^2)|-",
+            "(#ride([1,0]^*)*#turn([0,1]))|-/",
+        ] {
+            assert_bidirectional_matches(spec);
+        }
+
+        let board = &board();
+        let knight = &MoveGraph::from("[1,2]|-/".parse::<MoveCompact>().unwrap());
+        assert_eq!(
+            check_move_bidirectional(
+                knight,
+                &ReverseMoveGraph::from(knight),
+                board,
+                (4, 4),
+                (5, 6),
+                false,
+                false
+            ),
+            Some(vec![(4, 4), (5, 6)])
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bidirectional_exhaustive() {
+        for spec in BIDIRECTIONAL_SPECS {
+            assert_bidirectional_matches(spec);
+        }
+    }
+}